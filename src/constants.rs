@@ -144,8 +144,18 @@ pub enum VoiceOpCode {
     SessionDescription = 4,
     /// Used to indicate which users are speaking.
     Speaking = 5,
+    /// Used to acknowledge a keepalive.
+    HeartbeatAck = 6,
+    /// Used to resume a dropped websocket connection.
+    Resume = 7,
     /// Used to heartbeat.
     Heartbeat = 8,
+    /// Used to indicate that a resume succeeded.
+    Resumed = 9,
+    /// Used to indicate that a user has connected to the voice channel.
+    ClientConnect = 12,
+    /// Used to indicate that a user has disconnected from the voice channel.
+    ClientDisconnect = 13,
 }
 
 enum_number!(
@@ -156,7 +166,12 @@ enum_number!(
         KeepAlive,
         SessionDescription,
         Speaking,
+        HeartbeatAck,
+        Resume,
         Heartbeat,
+        Resumed,
+        ClientConnect,
+        ClientDisconnect,
     }
 );
 
@@ -169,7 +184,12 @@ impl VoiceOpCode {
             VoiceOpCode::KeepAlive => 3,
             VoiceOpCode::SessionDescription => 4,
             VoiceOpCode::Speaking => 5,
+            VoiceOpCode::HeartbeatAck => 6,
+            VoiceOpCode::Resume => 7,
             VoiceOpCode::Heartbeat => 8,
+            VoiceOpCode::Resumed => 9,
+            VoiceOpCode::ClientConnect => 12,
+            VoiceOpCode::ClientDisconnect => 13,
         }
     }
 }
@@ -220,3 +240,51 @@ pub mod close_codes {
     /// Cannot reconnect.
     pub const SHARDING_REQUIRED: u16 = 4011;
 }
+
+pub mod voice_close_codes {
+    /// An invalid voice OP Code was sent.
+    ///
+    /// Can resume.
+    pub const UNKNOWN_OPCODE: u16 = 4001;
+    /// A payload was sent prior to identifying.
+    ///
+    /// Cannot reconnect.
+    pub const NOT_AUTHENTICATED: u16 = 4003;
+    /// The token sent with the identify payload was incorrect.
+    ///
+    /// Cannot reconnect.
+    pub const AUTHENTICATION_FAILED: u16 = 4004;
+    /// More than one identify payload was sent.
+    ///
+    /// Can reconnect.
+    pub const ALREADY_AUTHENTICATED: u16 = 4005;
+    /// The session is no longer valid.
+    ///
+    /// Can reconnect.
+    pub const SESSION_NO_LONGER_VALID: u16 = 4006;
+    /// A session timed out.
+    ///
+    /// Can reconnect.
+    pub const SESSION_TIMEOUT: u16 = 4009;
+    /// The voice server couldn't be found.
+    ///
+    /// Cannot reconnect.
+    pub const SERVER_NOT_FOUND: u16 = 4011;
+    /// An unrecognised protocol was selected.
+    ///
+    /// Cannot reconnect.
+    pub const UNKNOWN_PROTOCOL: u16 = 4012;
+    /// The client was disconnected from the channel, by being kicked or having
+    /// the channel deleted.
+    ///
+    /// Cannot reconnect.
+    pub const DISCONNECTED: u16 = 4014;
+    /// The voice server crashed.
+    ///
+    /// Can resume.
+    pub const VOICE_SERVER_CRASHED: u16 = 4015;
+    /// An unrecognised encryption mode was selected.
+    ///
+    /// Cannot reconnect.
+    pub const UNKNOWN_ENCRYPTION_MODE: u16 = 4016;
+}
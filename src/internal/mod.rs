@@ -14,4 +14,4 @@ pub mod ws_impl;
 mod timer;
 
 #[cfg(feature = "voice")]
-pub use self::timer::Timer;
+pub use self::timer::{nanos, Tick, Timer};
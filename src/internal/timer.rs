@@ -1,47 +1,76 @@
-use chrono::{DateTime, Duration, Utc};
 use std::{
-    time::Duration as StdDuration,
-    thread
+    thread,
+    time::{Duration, Instant}
 };
 
+/// How a call to [`Timer::await`] went.
+///
+/// [`Timer::await`]: struct.Timer.html#method.await
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Tick {
+    /// The tick was reached by sleeping.
+    OnTime,
+    /// The tick had already passed, and so was returned immediately.
+    Late,
+    /// Whole periods had passed since the tick, and the given number of ticks
+    /// were skipped rather than returned back to back.
+    Skipped(u32),
+}
+
+/// A periodic timer measured against a monotonic clock, so that it is
+/// unaffected by changes to the system time.
+///
+/// Each tick is due a whole period after the last, rather than after the
+/// timer was last awaited, so that time spent between ticks does not make the
+/// timer drift.
 #[derive(Debug)]
 pub struct Timer {
-    due: DateTime<Utc>,
+    due: Instant,
     duration: Duration,
 }
 
 impl Timer {
     pub fn new(duration_in_ms: u64) -> Timer {
-        let duration = Duration::milliseconds(duration_in_ms as i64);
+        let duration = Duration::from_millis(duration_in_ms);
 
         Timer {
-            due: Utc::now() + duration,
+            due: Instant::now() + duration,
             duration,
         }
     }
 
-    pub fn await(&mut self) {
-        let due_time = (self.due.timestamp() * 1000) + i64::from(self.due.timestamp_subsec_millis());
-        let now_time = {
-            let now = Utc::now();
+    /// Sleeps until the next tick is due.
+    ///
+    /// If the timer has fallen more than a whole period behind, such as after
+    /// the thread was stalled, then the missed ticks are skipped so that later
+    /// ticks keep their spacing.
+    pub fn await(&mut self) -> Tick {
+        let now = Instant::now();
 
-            (now.timestamp() * 1000) + i64::from(now.timestamp_subsec_millis())
-        };
+        let tick = if self.due > now {
+            thread::sleep(self.due - now);
 
-        if due_time > now_time {
-            let sleep_time = due_time - now_time;
+            Tick::OnTime
+        } else {
+            let missed = (nanos(now - self.due) / nanos(self.duration).max(1)) as u32;
+
+            if missed > 0 {
+                self.due += self.duration * missed;
 
-            if sleep_time > 0 {
-                thread::sleep(StdDuration::from_millis(sleep_time as u64));
+                Tick::Skipped(missed)
+            } else {
+                Tick::Late
             }
-        }
+        };
+
+        self.due += self.duration;
 
-        self.due = self.due + self.duration;
+        tick
     }
 
     pub fn check(&mut self) -> bool {
-        if Utc::now() >= self.due {
-            self.due = self.due + self.duration;
+        if Instant::now() >= self.due {
+            self.due += self.duration;
 
             true
         } else {
@@ -49,5 +78,41 @@ impl Timer {
         }
     }
 
-    pub fn reset(&mut self) { self.due = Utc::now() + self.duration; }
+    pub fn reset(&mut self) { self.due = Instant::now() + self.duration; }
+}
+
+/// The whole length of a duration, in nanoseconds.
+pub fn nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+    use super::*;
+
+    #[test]
+    fn late_tick_is_returned_immediately() {
+        let mut timer = Timer::new(40);
+
+        thread::sleep(Duration::from_millis(50));
+
+        assert_eq!(timer.await(), Tick::Late);
+        assert_eq!(timer.await(), Tick::OnTime);
+    }
+
+    #[test]
+    fn missed_ticks_are_skipped() {
+        let mut timer = Timer::new(20);
+
+        thread::sleep(Duration::from_millis(70));
+
+        match timer.await() {
+            Tick::Skipped(missed) => assert!(missed >= 2, "skipped {}", missed),
+            other => panic!("expected skipped ticks, got {:?}", other),
+        }
+
+        // Later ticks keep their spacing rather than catching up.
+        assert_eq!(timer.await(), Tick::OnTime);
+    }
 }
@@ -1787,6 +1787,21 @@ impl<'de> Deserialize<'de> for EventType {
     }
 }
 
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct VoiceClientConnect {
+    pub audio_ssrc: u32,
+    pub user_id: UserId,
+    #[serde(default)]
+    pub video_ssrc: u32,
+}
+
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct VoiceClientDisconnect {
+    pub user_id: UserId,
+}
+
 #[allow(missing_docs)]
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 pub struct VoiceHeartbeat {
@@ -1813,7 +1828,7 @@ pub struct VoiceSessionDescription {
 #[allow(missing_docs)]
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 pub struct VoiceSpeaking {
-    pub speaking: bool,
+    pub speaking: SpeakingState,
     pub ssrc: u32,
     pub user_id: UserId,
 }
@@ -1824,8 +1839,16 @@ pub struct VoiceSpeaking {
 #[derive(Clone, Debug, Serialize)]
 #[serde(untagged)]
 pub enum VoiceEvent {
+    /// A user has connected to the voice channel, giving the SSRC their audio
+    /// will be sent with.
+    ClientConnect(VoiceClientConnect),
+    /// A user has disconnected from the voice channel.
+    ClientDisconnect(VoiceClientDisconnect),
     /// A voice heartbeat.
     Heartbeat(VoiceHeartbeat),
+    /// An acknowledgement of a keepalive, containing the nonce it was sent
+    /// with.
+    HeartbeatAck(u64),
     /// A "hello" was received with initial voice data, such as the
     /// [`heartbeat_interval`].
     ///
@@ -1835,6 +1858,8 @@ pub enum VoiceEvent {
     KeepAlive,
     /// A voice event describing the current session.
     Ready(VoiceSessionDescription),
+    /// A voice event denoting that a dropped connection was resumed.
+    Resumed,
     /// A voice event denoting that someone is speaking.
     Speaking(VoiceSpeaking),
     /// An unknown voice event not registered.
@@ -1857,6 +1882,16 @@ impl<'de> Deserialize<'de> for VoiceEvent {
             .map_err(DeError::custom)?;
 
         Ok(match op {
+            VoiceOpCode::ClientConnect => {
+                let v = VoiceClientConnect::deserialize(v).map_err(DeError::custom)?;
+
+                VoiceEvent::ClientConnect(v)
+            },
+            VoiceOpCode::ClientDisconnect => {
+                let v = VoiceClientDisconnect::deserialize(v).map_err(DeError::custom)?;
+
+                VoiceEvent::ClientDisconnect(v)
+            },
             VoiceOpCode::Heartbeat => {
                 let v = serde_json::from_value(v).map_err(DeError::custom)?;
 
@@ -1867,6 +1902,10 @@ impl<'de> Deserialize<'de> for VoiceEvent {
 
                 VoiceEvent::Hello(v)
             },
+            VoiceOpCode::HeartbeatAck => match v.as_u64() {
+                Some(nonce) => VoiceEvent::HeartbeatAck(nonce),
+                None => VoiceEvent::Unknown(VoiceOpCode::HeartbeatAck, v),
+            },
             VoiceOpCode::KeepAlive => VoiceEvent::KeepAlive,
             VoiceOpCode::SessionDescription => {
                 let v = VoiceSessionDescription::deserialize(v)
@@ -1879,6 +1918,7 @@ impl<'de> Deserialize<'de> for VoiceEvent {
 
                 VoiceEvent::Speaking(v)
             },
+            VoiceOpCode::Resumed => VoiceEvent::Resumed,
             other => VoiceEvent::Unknown(other, v),
         })
     }
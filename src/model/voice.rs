@@ -1,5 +1,11 @@
 //! Representations of voice information.
 
+use serde::de::{Deserialize, Deserializer, Error as DeError, Visitor};
+use serde::ser::{Serialize, Serializer};
+use std::{
+    fmt::{Formatter, Result as FmtResult},
+    result::Result as StdResult
+};
 use super::id::{ChannelId, UserId};
 
 /// Information about an available voice region.
@@ -36,3 +42,81 @@ pub struct VoiceState {
     pub token: Option<String>,
     pub user_id: UserId,
 }
+
+/// The ways in which a user can be speaking in a voice channel.
+///
+/// An empty set means that the user is not speaking.
+#[derive(Copy, PartialEq, Eq, Clone, PartialOrd, Ord, Hash)]
+pub struct SpeakingState {
+    bits: u8
+}
+
+__impl_bitflags! {
+    SpeakingState: u8 {
+        /// Normal transmission of voice audio.
+        MICROPHONE = 0b0000_0001;
+        /// Transmission of context audio for video, such as music, which
+        /// doesn't show the user's speaking indicator.
+        SOUNDSHARE = 0b0000_0010;
+        /// Priority speech, which lowers the volume of other speakers for
+        /// those listening.
+        PRIORITY = 0b0000_0100;
+    }
+}
+
+impl SpeakingState {
+    /// Shorthand for checking that the state contains the [Microphone] flag.
+    ///
+    /// [Microphone]: #associatedconstant.MICROPHONE
+    pub fn microphone(&self) -> bool { self.contains(Self::MICROPHONE) }
+
+    /// Shorthand for checking that the state contains the [Soundshare] flag.
+    ///
+    /// [Soundshare]: #associatedconstant.SOUNDSHARE
+    pub fn soundshare(&self) -> bool { self.contains(Self::SOUNDSHARE) }
+
+    /// Shorthand for checking that the state contains the [Priority] flag.
+    ///
+    /// [Priority]: #associatedconstant.PRIORITY
+    pub fn priority(&self) -> bool { self.contains(Self::PRIORITY) }
+
+    /// Whether the user is speaking in any way.
+    pub fn is_speaking(&self) -> bool { !self.is_empty() }
+}
+
+impl<'de> Deserialize<'de> for SpeakingState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> StdResult<Self, D::Error> {
+        deserializer.deserialize_any(SpeakingStateVisitor)
+    }
+}
+
+impl Serialize for SpeakingState {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+        where S: Serializer {
+        serializer.serialize_u8(self.bits())
+    }
+}
+
+/// Older versions of the voice gateway give speaking states as a bool, which
+/// is accepted as either the microphone flag or no flags.
+struct SpeakingStateVisitor;
+
+impl<'de> Visitor<'de> for SpeakingStateVisitor {
+    type Value = SpeakingState;
+
+    fn expecting(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter.write_str("a speaking bitfield or bool")
+    }
+
+    fn visit_bool<E: DeError>(self, value: bool) -> StdResult<SpeakingState, E> {
+        Ok(if value { SpeakingState::MICROPHONE } else { SpeakingState::empty() })
+    }
+
+    fn visit_i64<E: DeError>(self, value: i64) -> StdResult<SpeakingState, E> {
+        Ok(SpeakingState::from_bits_truncate(value as u8))
+    }
+
+    fn visit_u64<E: DeError>(self, value: u64) -> StdResult<SpeakingState, E> {
+        Ok(SpeakingState::from_bits_truncate(value as u8))
+    }
+}
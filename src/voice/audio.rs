@@ -1,13 +1,38 @@
+use model::voice::SpeakingState;
+use opus::Decoder as OpusDecoder;
 use parking_lot::Mutex;
 use std::{
     sync::Arc,
     time::Duration
 };
+use super::events::{EventStore, TrackError};
+use super::filters::{AudioFilter, FilterAction, GainRamp};
+use super::metadata::AudioMetadata;
+use super::streamer::{decode_and_add, lazy_decoder};
+use super::VoiceError;
 
 pub const HEADER_LEN: usize = 12;
 pub const SAMPLE_RATE: u32 = 48_000;
 pub static SILENT_FRAME: [u8; 3] = [0xf8, 0xff, 0xfe];
 
+/// Length of a single audio frame, in milliseconds.
+pub const FRAME_LEN_MS: u64 = 20;
+
+/// Samples per channel in one frame.
+pub const FRAME_SAMPLES: usize = 960;
+
+/// The number of whole frames in a duration.
+pub(crate) fn duration_to_frames(time: Duration) -> u64 {
+    let millis = time.as_secs() * 1000 + u64::from(time.subsec_nanos() / 1_000_000);
+
+    millis / FRAME_LEN_MS
+}
+
+/// The duration of a number of frames.
+pub(crate) fn frames_to_duration(frames: u64) -> Duration {
+    Duration::from_millis(frames * FRAME_LEN_MS)
+}
+
 /// A readable audio source.
 pub trait AudioSource: Send {
     fn is_stereo(&mut self) -> bool;
@@ -17,18 +42,142 @@ pub trait AudioSource: Send {
     fn read_pcm_frame(&mut self, buffer: &mut [i16]) -> Option<usize>;
 
     fn read_opus_frame(&mut self) -> Option<Vec<u8>>;
+
+    /// Decodes the next Opus frame, adding the resulting stereo samples into
+    /// `float_buffer` scaled by `volume`.
+    ///
+    /// `decoder` keeps the decoder's state from one frame of the stream to
+    /// the next, and is created on first use.
+    ///
+    /// Returns the number of samples decoded per channel, where `0` marks the
+    /// end of the stream. `None` should only be returned if the source could
+    /// not be read, as this ends the track; a frame which can't be decoded is
+    /// instead skipped as silence.
+    ///
+    /// This is used in place of [`read_opus_frame`] whenever the frame can't be
+    /// sent as-is, i.e., when mixing with other sources or changing volume.
+    /// The default implementation decodes the frame returned by
+    /// [`read_opus_frame`].
+    ///
+    /// [`read_opus_frame`]: #tymethod.read_opus_frame
+    fn decode_and_add_opus_frame(&mut self,
+                                 decoder: &mut Option<OpusDecoder>,
+                                 float_buffer: &mut [f32; 1920],
+                                 volume: f32)
+                                 -> Option<usize> {
+        let frame = self.read_opus_frame()?;
+
+        if frame.is_empty() {
+            return Some(0);
+        }
+
+        decode_and_add(lazy_decoder(decoder)?, &frame, float_buffer, volume)
+    }
+
+    /// Whether this source is able to change its playback position.
+    ///
+    /// Sources which are not seekable will ignore calls to [`seek_time`].
+    ///
+    /// [`seek_time`]: #method.seek_time
+    fn is_seekable(&self) -> bool { false }
+
+    /// Moves the read position of this source to the frame containing `time`,
+    /// measured from the start of the stream.
+    ///
+    /// Returns the position which was actually reached, or `None` if the
+    /// source could not be seeked.
+    fn seek_time(&mut self, _time: Duration) -> Option<Duration> { None }
+
+    /// Information about the track being read, such as its title and length,
+    /// where known.
+    fn metadata(&self) -> AudioMetadata { AudioMetadata::default() }
+
+    /// Takes the error which caused the source to stop producing frames, for
+    /// sources able to say more than that a read failed.
+    fn take_error(&mut self) -> Option<VoiceError> { None }
 }
 
 /// A receiver for incoming audio.
 pub trait AudioReceiver: Send {
     fn speaking_update(&mut self, ssrc: u32, user_id: u64, speaking: bool);
 
+    /// Called with each 20ms frame of a user's decoded audio.
+    ///
+    /// Frames are delivered in sequence order, once every 20ms per speaking
+    /// user. Lost packets are concealed, so each delivered frame follows on
+    /// from the last.
     fn voice_packet(&mut self,
                     ssrc: u32,
                     sequence: u16,
                     timestamp: u32,
                     stereo: bool,
                     data: &[i16]);
+
+    /// Called with the raw Opus data of each received packet, just before its
+    /// decoded audio is passed to [`voice_packet`].
+    ///
+    /// Concealed frames have no packet, and so are only passed to
+    /// [`voice_packet`].
+    ///
+    /// [`voice_packet`]: #tymethod.voice_packet
+    fn opus_packet(&mut self, _ssrc: u32, _sequence: u16, _timestamp: u32, _data: &[u8]) {}
+
+    /// Called once every 20ms while connected, after any received frames have
+    /// been passed on, whether or not anything was received.
+    ///
+    /// This lets a receiver notice users who have stopped sending audio,
+    /// without waiting for a packet from anyone else.
+    fn tick(&mut self) {}
+
+    /// Called alongside [`speaking_update`], with the full set of flags
+    /// describing how the user is speaking.
+    ///
+    /// [`speaking_update`]: #tymethod.speaking_update
+    fn speaking_state(&mut self, _ssrc: u32, _user_id: u64, _state: SpeakingState) {}
+
+    /// Called when a user joins the voice channel, with the SSRC their audio
+    /// will be sent with.
+    ///
+    /// This arrives before they first speak, so can be used to map SSRCs to
+    /// users ahead of any [`voice_packet`]s.
+    ///
+    /// [`voice_packet`]: #tymethod.voice_packet
+    fn client_connect(&mut self, _ssrc: u32, _user_id: u64) {}
+
+    /// Called when a user leaves the voice channel.
+    ///
+    /// No more audio will be received from the SSRC they were using, unless
+    /// it is given to another user by a later [`client_connect`].
+    ///
+    /// [`client_connect`]: #method.client_connect
+    fn client_disconnect(&mut self, _user_id: u64) {}
+
+    /// Called when Discord moves the connection to a different voice server,
+    /// once the connection to the new server is ready.
+    ///
+    /// Every user is given a new SSRC on the new server, which will be
+    /// announced through [`client_connect`] and [`speaking_update`].
+    ///
+    /// [`client_connect`]: #method.client_connect
+    /// [`speaking_update`]: #tymethod.speaking_update
+    fn region_changed(&mut self, _old_endpoint: &str, _new_endpoint: &str) {}
+}
+
+/// How many more times an [`Audio`] should repeat once it reaches its end.
+///
+/// [`Audio`]: struct.Audio.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LoopState {
+    /// The track repeats until it is stopped.
+    Infinite,
+    /// The track repeats the given number of further times.
+    ///
+    /// `Finite(0)` plays the track through only once.
+    Finite(usize),
+}
+
+impl Default for LoopState {
+    fn default() -> Self { LoopState::Finite(0) }
 }
 
 #[derive(Clone, Copy)]
@@ -60,6 +209,20 @@ pub enum AudioType {
 /// }
 /// ```
 ///
+/// Callbacks can also be attached to a track, to be run by the voice thread
+/// when it ends, errors, or reaches a given position:
+///
+/// ```rust,ignore
+/// use std::time::Duration;
+///
+/// let mut audio = safe_audio.lock();
+///
+/// audio
+///     .on_end(|_| println!("Track finished."))
+///     .at_position(Duration::from_secs(90), |_| println!("1:30 reached."))
+///     .every(Duration::from_secs(1), |audio| println!("At {:?}", audio.position));
+/// ```
+///
 /// [`LockedAudio`]: type.LockedAudio.html
 /// [`Handler::play_only`]: struct.Handler.html#method.play_only
 /// [`Handler::play_returning`]: struct.Handler.html#method.play_returning
@@ -82,6 +245,34 @@ pub struct Audio {
     /// [`volume`]: #method.volume
     pub volume: f32,
 
+    /// How many more times the track will repeat, counting down as each
+    /// repeat begins.
+    ///
+    /// Looping requires the underlying source to be seekable, as it is
+    /// rewound to its start. Can be controlled with [`loops`] if chaining is
+    /// desired.
+    ///
+    /// [`loops`]: #method.loops
+    pub loops: LoopState,
+
+    /// Whether the track is quietened while other users talk, if ducking has
+    /// been enabled through [`Handler::set_ducking`].
+    ///
+    /// Can be controlled with [`duck`] if chaining is desired.
+    ///
+    /// [`Handler::set_ducking`]: struct.Handler.html#method.set_ducking
+    /// [`duck`]: #method.duck
+    pub ducked: bool,
+
+    /// The error which stopped the track, if its source was able to give one.
+    ///
+    /// This is set before a [`TrackError::Read`] event is raised, so may be
+    /// taken from within an [`on_error`] callback.
+    ///
+    /// [`TrackError::Read`]: enum.TrackError.html#variant.Read
+    /// [`on_error`]: #method.on_error
+    pub error: Option<VoiceError>,
+
     /// Whether or not the sound has finished, or reached the end of its stream.
     ///
     /// ***Read-only*** for now.
@@ -92,11 +283,42 @@ pub struct Audio {
     /// *Calling code is not expected to use this.*
     pub source: Box<AudioSource>,
 
+    /// Information about the track, taken from its source when the track was
+    /// created.
+    ///
+    /// This is filled in by sources such as [`ffmpeg`] and [`ytdl`], and may
+    /// be changed freely, e.g. to add a title to a track which has none.
+    ///
+    /// [`ffmpeg`]: fn.ffmpeg.html
+    /// [`ytdl`]: fn.ytdl.html
+    pub metadata: AudioMetadata,
+
     /// The current position for playback.
     ///
-    /// Consider the position fields **read-only** for now.
+    /// Consider the position fields **read-only** for now. Use [`position`]
+    /// to request a seek.
+    ///
+    /// [`position`]: #method.position
     pub position: Duration,
+    /// Whether a seek has been requested, but not yet carried out by the
+    /// voice thread.
     pub position_modified: bool,
+    /// Target of the most recent seek request.
+    seek_target: Duration,
+
+    /// Effects applied to each frame of this track, in order.
+    ///
+    /// Can be added to with [`add_filter`] if chaining is desired.
+    ///
+    /// [`add_filter`]: #method.add_filter
+    pub filters: Vec<Box<AudioFilter>>,
+
+    /// Event callbacks attached to this track.
+    pub(crate) events: EventStore,
+
+    /// Decodes the source's Opus frames when they need to be mixed, created
+    /// on first use.
+    pub(crate) decoder: Option<OpusDecoder>,
 }
 
 impl Audio {
@@ -104,10 +326,18 @@ impl Audio {
         Self {
             playing: true,
             volume: 1.0,
+            loops: LoopState::default(),
+            ducked: false,
+            error: None,
             finished: false,
+            metadata: source.metadata(),
             source,
             position: Duration::new(0, 0),
             position_modified: false,
+            seek_target: Duration::new(0, 0),
+            filters: Vec::new(),
+            events: EventStore::default(),
+            decoder: None,
         }
     }
 
@@ -138,22 +368,238 @@ impl Audio {
         self
     }
 
+    /// Sets [`ducked`] in a manner that allows method chaining.
+    ///
+    /// [`ducked`]: #structfield.ducked
+    pub fn duck(&mut self, ducked: bool) -> &mut Self {
+        self.ducked = ducked;
+
+        self
+    }
+
+    /// Sets [`loops`] in a manner that allows method chaining.
+    ///
+    /// [`loops`]: #structfield.loops
+    pub fn loops(&mut self, loops: LoopState) -> &mut Self {
+        self.loops = loops;
+
+        self
+    }
+
     /// Change the position in the stream for subsequent playback.
     ///
-    /// Currently a No-op.
+    /// The seek is carried out by the voice thread before the next frame is
+    /// read. If the underlying source is not seekable (see [`is_seekable`]),
+    /// then the request is dropped and playback continues uninterrupted.
+    ///
+    /// [`is_seekable`]: #method.is_seekable
     pub fn position(&mut self, position: Duration) -> &mut Self {
-        self.position = position;
+        self.seek_target = position;
         self.position_modified = true;
 
         self
     }
 
+    /// Appends an effect to [`filters`] in a manner that allows method
+    /// chaining.
+    ///
+    /// [`filters`]: #structfield.filters
+    pub fn add_filter<F: AudioFilter + 'static>(&mut self, filter: F) -> &mut Self {
+        self.filters.push(Box::new(filter));
+
+        self
+    }
+
+    /// Fades this track in from silence over the given `duration`, starting
+    /// from the next frame.
+    pub fn fade_in(&mut self, duration: Duration) -> &mut Self {
+        self.add_filter(GainRamp::fade_in(duration))
+    }
+
+    /// Fades this track out to silence over the given `duration`, starting
+    /// from the next frame, then stops it.
+    pub fn fade_out(&mut self, duration: Duration) -> &mut Self {
+        self.add_filter(GainRamp::fade_out(duration))
+    }
+
+    /// Registers a callback to be run once this track stops playing, whether
+    /// it reached the end of its stream, failed, or was stopped.
+    pub fn on_end<F>(&mut self, callback: F) -> &mut Self
+        where F: FnMut(&mut Audio) + Send + 'static {
+        self.events.add_end(Box::new(callback));
+
+        self
+    }
+
+    /// Registers a callback to be run each time this track raises an error.
+    ///
+    /// A [`TrackError::Read`] is always followed by the track ending.
+    ///
+    /// [`TrackError::Read`]: enum.TrackError.html#variant.Read
+    pub fn on_error<F>(&mut self, callback: F) -> &mut Self
+        where F: FnMut(&mut Audio, TrackError) + Send + 'static {
+        self.events.add_error(Box::new(callback));
+
+        self
+    }
+
+    /// Registers a callback to be run when playback reaches the given
+    /// `position`.
+    ///
+    /// If the position has already been passed, then the callback is run the
+    /// next time the voice thread dispatches events, every 20ms, without
+    /// waiting for another frame to be played. The callback stays registered,
+    /// and runs again whenever playback passes the position anew, such as
+    /// after seeking back before it or looping.
+    pub fn at_position<F>(&mut self, position: Duration, callback: F) -> &mut Self
+        where F: FnMut(&mut Audio) + Send + 'static {
+        self.events.add_position(position, Box::new(callback));
+
+        self
+    }
+
+    /// Registers a callback to be run each time playback advances by another
+    /// `period`, for as long as the track plays.
+    ///
+    /// Ticks are counted from the start of the track, and are counted again
+    /// from the new position after seeking backwards or looping.
+    pub fn every<F>(&mut self, period: Duration, callback: F) -> &mut Self
+        where F: FnMut(&mut Audio) + Send + 'static {
+        self.events.add_periodic(period, Box::new(callback));
+
+        self
+    }
+
+    /// Whether the underlying source supports changing its [`position`].
+    ///
+    /// [`position`]: #method.position
+    pub fn is_seekable(&self) -> bool { self.source.is_seekable() }
+
+    /// Carries out a pending seek request, if there is one.
+    ///
+    /// Returns `false` if the source could not be seeked, in which case a
+    /// [`TrackError::Seek`] event is raised.
+    ///
+    /// *Used internally*.
+    ///
+    /// [`TrackError::Seek`]: enum.TrackError.html#variant.Seek
+    pub(crate) fn apply_seek(&mut self) -> bool {
+        if !self.position_modified {
+            return true;
+        }
+
+        self.position_modified = false;
+
+        match self.source.seek_time(self.seek_target) {
+            Some(position) => {
+                self.position = position;
+                self.reset_decoder();
+
+                true
+            },
+            None => {
+                self.events.raise_error(TrackError::Seek);
+
+                false
+            },
+        }
+    }
+
+    /// Marks this track as finished after its source failed to produce a
+    /// frame, raising a [`TrackError::Read`] event.
+    ///
+    /// *Used internally*.
+    ///
+    /// [`TrackError::Read`]: enum.TrackError.html#variant.Read
+    pub(crate) fn fail(&mut self) {
+        self.error = self.source.take_error();
+        self.finished = true;
+        self.events.raise_error(TrackError::Read);
+    }
+
+    /// Rewinds the track to play again from its start.
+    ///
+    /// Returns `false` if the source could not be seeked.
+    ///
+    /// *Used internally*.
+    pub(crate) fn rewind(&mut self) -> bool {
+        if self.source.seek_time(Duration::new(0, 0)).is_none() {
+            return false;
+        }
+
+        self.position = Duration::new(0, 0);
+        self.finished = false;
+        self.reset_decoder();
+
+        true
+    }
+
+    /// Rewinds the track if it has any [`loops`] remaining, after it has
+    /// reached its end.
+    ///
+    /// Returns `false` if the track should instead finish.
+    ///
+    /// *Used internally*.
+    ///
+    /// [`loops`]: #structfield.loops
+    pub(crate) fn try_loop(&mut self) -> bool {
+        if self.loops == LoopState::Finite(0) {
+            return false;
+        }
+
+        if !self.rewind() {
+            warn!("[Voice] Unable to loop a track whose source can't seek.");
+
+            return false;
+        }
+
+        if let LoopState::Finite(ref mut remaining) = self.loops {
+            *remaining -= 1;
+        }
+
+        true
+    }
+
+    /// Runs every filter over a frame of this track, removing those which have
+    /// completed.
+    ///
+    /// Returns `false` if a filter has stopped the track.
+    ///
+    /// *Used internally*.
+    pub(crate) fn apply_filters(&mut self, frame: &mut [f32; 1920]) -> bool {
+        let position = self.position;
+        let mut keep_playing = true;
+
+        let mut i = 0;
+
+        while i < self.filters.len() {
+            match self.filters[i].process(frame, position) {
+                FilterAction::Continue => i += 1,
+                FilterAction::Remove => {
+                    self.filters.remove(i);
+                },
+                FilterAction::StopTrack => {
+                    keep_playing = false;
+                    i += 1;
+                },
+            }
+        }
+
+        keep_playing
+    }
+
+    /// Clears any decoder state carried over from before a seek.
+    fn reset_decoder(&mut self) {
+        if let Some(ref mut decoder) = self.decoder {
+            let _ = decoder.reset_state();
+        }
+    }
+
     /// Steps playback location forward by one frame.
     ///
-    /// *Used internally*, although in future this might affect seek position.
+    /// *Used internally*.
     pub(crate) fn step_frame(&mut self) {
-        self.position += Duration::from_millis(20);
-        self.position_modified = false;
+        self.position += Duration::from_millis(FRAME_LEN_MS);
     }
 
 }
@@ -0,0 +1,248 @@
+use internal::prelude::*;
+use opus::{
+    Application as CodingMode,
+    Channels,
+    Encoder as OpusEncoder,
+};
+use std::{
+    sync::Arc,
+    time::Duration
+};
+use super::audio::{duration_to_frames, frames_to_duration, FRAME_SAMPLES, SAMPLE_RATE};
+use super::metadata::AudioMetadata;
+use super::{AudioSource, AudioType, VoiceError};
+
+enum CachedData {
+    Opus(Vec<Vec<u8>>),
+    /// Interleaved samples, split into frames when read.
+    Pcm(Vec<i16>),
+}
+
+struct CachedInner {
+    data: CachedData,
+    metadata: AudioMetadata,
+    stereo: bool,
+}
+
+impl CachedInner {
+    fn frame_samples(&self) -> usize {
+        if self.stereo { FRAME_SAMPLES * 2 } else { FRAME_SAMPLES }
+    }
+
+    fn frame_count(&self) -> usize {
+        match self.data {
+            CachedData::Opus(ref frames) => frames.len(),
+            CachedData::Pcm(ref samples) => {
+                let frame_samples = self.frame_samples();
+
+                (samples.len() + frame_samples - 1) / frame_samples
+            },
+        }
+    }
+}
+
+/// An audio track held entirely in memory, which can be played any number of
+/// times without reading its original source again.
+///
+/// The track is read once, when the cache is created. Each call to
+/// [`new_source`] then returns an independent, seekable source reading from
+/// the shared buffer, so the same clip can be played in many guilds at once.
+/// Cloning a `CachedAudio` is cheap.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use serenity::voice::{self, CachedAudio};
+///
+/// let clip = CachedAudio::compressed(voice::ffmpeg("airhorn.mp3")?)?;
+///
+/// // In any number of guilds...
+/// handler.play(clip.new_source());
+/// ```
+///
+/// [`new_source`]: #method.new_source
+#[derive(Clone)]
+pub struct CachedAudio {
+    inner: Arc<CachedInner>,
+}
+
+impl CachedAudio {
+    /// Reads a source to its end, storing its audio as-is.
+    ///
+    /// Opus sources are stored as their frames, while PCM sources are stored
+    /// uncompressed. See [`compressed`] to store PCM sources more compactly.
+    ///
+    /// [`compressed`]: #method.compressed
+    pub fn new(mut source: Box<AudioSource>) -> Result<Self> {
+        let stereo = source.is_stereo();
+        let metadata = source.metadata();
+
+        let data = match source.get_type() {
+            AudioType::Opus => CachedData::Opus(read_opus(&mut *source)?),
+            AudioType::Pcm => CachedData::Pcm(read_pcm(&mut *source, stereo)?),
+        };
+
+        Ok(Self {
+            inner: Arc::new(CachedInner {
+                data,
+                metadata,
+                stereo,
+            }),
+        })
+    }
+
+    /// Reads a source to its end, encoding PCM audio to Opus to save memory.
+    ///
+    /// Opus sources are stored as their frames, as with [`new`].
+    ///
+    /// [`new`]: #method.new
+    pub fn compressed(mut source: Box<AudioSource>) -> Result<Self> {
+        let stereo = source.is_stereo();
+        let metadata = source.metadata();
+
+        let frames = match source.get_type() {
+            AudioType::Opus => read_opus(&mut *source)?,
+            AudioType::Pcm => encode_pcm(&read_pcm(&mut *source, stereo)?, stereo)?,
+        };
+
+        Ok(Self {
+            inner: Arc::new(CachedInner {
+                data: CachedData::Opus(frames),
+                metadata,
+                stereo,
+            }),
+        })
+    }
+
+    /// The length of the stored audio.
+    pub fn duration(&self) -> Duration {
+        frames_to_duration(self.inner.frame_count() as u64)
+    }
+
+    /// Whether the stored audio is in stereo.
+    pub fn is_stereo(&self) -> bool { self.inner.stereo }
+
+    /// The metadata of the source the audio was read from.
+    pub fn metadata(&self) -> &AudioMetadata { &self.inner.metadata }
+
+    /// Creates a new source playing the stored audio from the start.
+    pub fn new_source(&self) -> Box<AudioSource> {
+        Box::new(CachedSource {
+            frame: 0,
+            inner: Arc::clone(&self.inner),
+        })
+    }
+}
+
+/// A reader over a [`CachedAudio`], with its own position.
+///
+/// [`CachedAudio`]: struct.CachedAudio.html
+struct CachedSource {
+    frame: usize,
+    inner: Arc<CachedInner>,
+}
+
+impl AudioSource for CachedSource {
+    fn is_stereo(&mut self) -> bool { self.inner.stereo }
+
+    fn get_type(&self) -> AudioType {
+        match self.inner.data {
+            CachedData::Opus(_) => AudioType::Opus,
+            CachedData::Pcm(_) => AudioType::Pcm,
+        }
+    }
+
+    fn read_pcm_frame(&mut self, buffer: &mut [i16]) -> Option<usize> {
+        let start = self.frame * self.inner.frame_samples();
+
+        let samples = match self.inner.data {
+            CachedData::Pcm(ref samples) => samples,
+            CachedData::Opus(_) => return None,
+        };
+
+        if start >= samples.len() {
+            return Some(0);
+        }
+
+        let len = buffer.len().min(samples.len() - start);
+        buffer[..len].copy_from_slice(&samples[start..start + len]);
+        self.frame += 1;
+
+        Some(len)
+    }
+
+    fn read_opus_frame(&mut self) -> Option<Vec<u8>> {
+        let frame = match self.inner.data {
+            CachedData::Opus(ref frames) => frames.get(self.frame).cloned().unwrap_or_default(),
+            CachedData::Pcm(_) => return None,
+        };
+
+        if !frame.is_empty() {
+            self.frame += 1;
+        }
+
+        Some(frame)
+    }
+
+    fn is_seekable(&self) -> bool { true }
+
+    fn seek_time(&mut self, time: Duration) -> Option<Duration> {
+        let target = duration_to_frames(time) as usize;
+
+        self.frame = target.min(self.inner.frame_count());
+
+        Some(frames_to_duration(self.frame as u64))
+    }
+
+    fn metadata(&self) -> AudioMetadata { self.inner.metadata.clone() }
+}
+
+fn read_opus(source: &mut AudioSource) -> Result<Vec<Vec<u8>>> {
+    let mut frames = Vec::new();
+
+    loop {
+        match source.read_opus_frame() {
+            Some(ref frame) if frame.is_empty() => return Ok(frames),
+            Some(frame) => frames.push(frame),
+            None => return Err(Error::Voice(VoiceError::SourceRead)),
+        }
+    }
+}
+
+fn read_pcm(source: &mut AudioSource, stereo: bool) -> Result<Vec<i16>> {
+    let mut samples = Vec::new();
+    let mut buffer = [0i16; FRAME_SAMPLES * 2];
+    let frame_samples = if stereo { FRAME_SAMPLES * 2 } else { FRAME_SAMPLES };
+
+    loop {
+        match source.read_pcm_frame(&mut buffer[..frame_samples]) {
+            Some(0) => return Ok(samples),
+            Some(len) => samples.extend_from_slice(&buffer[..len]),
+            None => return Err(Error::Voice(VoiceError::SourceRead)),
+        }
+    }
+}
+
+fn encode_pcm(samples: &[i16], stereo: bool) -> Result<Vec<Vec<u8>>> {
+    let channels = if stereo { Channels::Stereo } else { Channels::Mono };
+    let frame_samples = if stereo { FRAME_SAMPLES * 2 } else { FRAME_SAMPLES };
+
+    let mut encoder = OpusEncoder::new(SAMPLE_RATE, channels, CodingMode::Audio)?;
+    let mut frames = Vec::with_capacity(samples.len() / frame_samples + 1);
+    let mut input = vec![0i16; frame_samples];
+    let mut output = [0u8; 4000];
+
+    for chunk in samples.chunks(frame_samples) {
+        // The final frame is padded out with silence.
+        input[..chunk.len()].copy_from_slice(chunk);
+
+        for sample in &mut input[chunk.len()..] {
+            *sample = 0;
+        }
+
+        let len = encoder.encode(&input, &mut output)?;
+        frames.push(output[..len].to_vec());
+    }
+
+    Ok(frames)
+}
@@ -6,29 +6,30 @@ use byteorder::{
     WriteBytesExt
 };
 use constants::VOICE_GATEWAY_VERSION;
+use gateway::GatewayError;
 use internal::prelude::*;
 use internal::{
     ws_impl::{ReceiverExt, SenderExt},
+    Tick,
     Timer
 };
 use model::{
     event::VoiceEvent,
-    id::UserId
+    id::UserId,
+    voice::SpeakingState
 };
 use opus::{
     packet as opus_packet,
-    Application as CodingMode,
     Channels,
-    Decoder as OpusDecoder,
     Encoder as OpusEncoder,
     SoftClip,
 };
 use parking_lot::Mutex;
 use serde::Deserialize;
-use sodiumoxide::crypto::secretbox::{self, Key, Nonce};
+use sodiumoxide::crypto::secretbox::Key;
 use std::{
-    collections::HashMap,
-    io::Write,
+    collections::hash_map::{Entry, HashMap},
+    io::{ErrorKind as IoErrorKind, Write},
     net::{SocketAddr, ToSocketAddrs, UdpSocket},
     sync::{
         mpsc::{
@@ -43,13 +44,22 @@ use std::{
         Builder as ThreadBuilder, 
         JoinHandle
     },
-    time::Duration
+    time::{Duration, Instant}
 };
-use super::audio::{AudioReceiver, AudioType, LockedAudio, HEADER_LEN, SAMPLE_RATE, SILENT_FRAME};
+use super::audio::{AudioReceiver, AudioType, LockedAudio, HEADER_LEN, SILENT_FRAME};
 use super::connection_info::ConnectionInfo;
-use super::{payload, VoiceError, CRYPTO_MODE};
+use super::crypto::CryptoMode;
+use super::ducking::Ducker;
+use super::encoder::EncoderSettings;
+use super::filters::ramp_gain;
+use super::jitter::JitterBuffer;
+use super::stats::VoiceStats;
+use super::streamer::{decode_and_add, lazy_decoder};
+use super::vad;
+use super::{payload, VoiceError};
 use websocket::{
     client::Url as WebsocketUrl,
+    result::WebSocketError,
     sync::{
         client::ClientBuilder,
         stream::{
@@ -66,6 +76,8 @@ type Client = WsClient<TlsStream<TcpStream>>;
 enum ReceiverStatus {
     Udp(Vec<u8>),
     Websocket(VoiceEvent),
+    /// The websocket connection was dropped, for the given reason.
+    Disconnected(Error),
 }
 
 #[allow(dead_code)]
@@ -81,28 +93,54 @@ struct ThreadItems {
 pub struct Connection {
     audio_timer: Timer,
     client: Arc<Mutex<Client>>,
-    decoder_map: HashMap<(u32, Channels), OpusDecoder>,
+    crypto_mode: CryptoMode,
+    /// Whether received audio is decoded even without a receiver.
+    decode_all: bool,
     destination: SocketAddr,
     encoder: OpusEncoder,
-    encoder_stereo: bool,
+    encoder_settings: EncoderSettings,
+    info: ConnectionInfo,
+    jitter_buffers: HashMap<u32, JitterBuffer>,
     keepalive_timer: Timer,
     key: Key,
+    last_heartbeat: Option<(u64, Instant)>,
+    lite_nonce: u32,
+    packet: [u8; 512],
     sequence: u16,
     silence_frames: u8,
     soft_clip: SoftClip,
+    /// The SSRCs of the other users Discord reports as speaking, other than
+    /// those only sharing sound, and when audio was last received from each.
+    speakers: HashMap<u32, Instant>,
     speaking: bool,
+    /// The flags sent while audio is being sent.
+    speaking_state: SpeakingState,
     ssrc: u32,
-    thread_items: ThreadItems,
+    stats: VoiceStats,
+    /// Not present if the sockets are polled by the connection itself.
+    thread_items: Option<ThreadItems>,
     timestamp: u32,
     udp: UdpSocket,
     user_id: UserId,
+    user_ssrcs: HashMap<UserId, u32>,
+    /// Whether a dropped websocket has been reported, when it is polled by
+    /// the connection itself.
+    ws_reported: bool,
 }
 
 impl Connection {
-    pub fn new(mut info: ConnectionInfo) -> Result<Connection> {
+    /// Establishes a voice connection.
+    ///
+    /// If `polled` is set, the UDP socket and websocket are made non-blocking
+    /// and read from on each call to [`mix`], rather than from threads of
+    /// their own.
+    ///
+    /// [`mix`]: #method.mix
+    pub fn new(mut info: ConnectionInfo, polled: bool) -> Result<Connection> {
         let url = generate_url(&mut info.endpoint)?;
 
         let mut client = ClientBuilder::from_url(&url).connect_secure(None)?;
+        set_handshake_timeout(&mut client)?;
         client.send_json(&payload::build_identify(&info))?;
 
         let hello = loop {
@@ -124,9 +162,8 @@ impl Connection {
             }
         };
 
-        if !has_valid_mode(&hello.modes) {
-            return Err(Error::Voice(VoiceError::VoiceModeUnavailable));
-        }
+        let crypto_mode = CryptoMode::negotiate(&hello.modes)
+            .ok_or(Error::Voice(VoiceError::VoiceModeUnavailable))?;
 
         let destination = (&info.endpoint[..], hello.port)
             .to_socket_addrs()?
@@ -166,134 +203,160 @@ impl Connection {
             let port = (&bytes[port_pos..]).read_u16::<LittleEndian>()?;
 
             client
-                .send_json(&payload::build_select_protocol(addr, port))?;
+                .send_json(&payload::build_select_protocol(addr, port, crypto_mode))?;
         }
 
-        let key = encryption_key(&mut client)?;
+        let key = encryption_key(&mut client, crypto_mode)?;
 
-        let _ = client
-            .stream_ref()
-            .as_tcp()
-            .set_read_timeout(Some(Duration::from_millis(25)));
+        set_polling(&mut client, polled)?;
 
         let mutexed_client = Arc::new(Mutex::new(client));
-        let thread_items = start_threads(Arc::clone(&mutexed_client), &udp)?;
+        let thread_items = if polled {
+            udp.set_nonblocking(true)?;
+
+            None
+        } else {
+            Some(start_threads(Arc::clone(&mutexed_client), &udp)?)
+        };
 
         info!("[Voice] Connected to: {}", info.endpoint);
 
-        let encoder = OpusEncoder::new(SAMPLE_RATE, Channels::Mono, CodingMode::Audio)?;
+        let encoder_settings = EncoderSettings::default();
+        let encoder = encoder_settings.build()?;
 
         let soft_clip = SoftClip::new(Channels::Stereo);
 
         // Per discord dev team's current recommendations:
         // (https://discordapp.com/developers/docs/topics/voice-connections#heartbeating)
         let temp_heartbeat = (hello.heartbeat_interval as f64 * 0.75) as u64;
+        let user_id = info.user_id;
 
         Ok(Connection {
             audio_timer: Timer::new(1000 * 60 * 4),
             client: mutexed_client,
-            decoder_map: HashMap::new(),
+            crypto_mode,
+            decode_all: false,
             destination,
             encoder,
-            encoder_stereo: false,
+            encoder_settings,
+            info,
+            jitter_buffers: HashMap::new(),
             key,
             keepalive_timer: Timer::new(temp_heartbeat),
+            last_heartbeat: None,
+            lite_nonce: 0,
+            packet: [0; 512],
             udp,
             sequence: 0,
             // We need to send some frames to receive any audio.
             silence_frames: 100,
             soft_clip,
+            speakers: HashMap::new(),
             speaking: false,
+            speaking_state: SpeakingState::MICROPHONE,
             ssrc: hello.ssrc,
+            stats: VoiceStats::default(),
             thread_items,
             timestamp: 0,
-            user_id: info.user_id,
+            user_id,
+            user_ssrcs: HashMap::new(),
+            ws_reported: false,
         })
     }
 
-    #[allow(unused_variables)]
-    pub fn cycle(&mut self,
-                 sources: &mut Vec<LockedAudio>,
-                 receiver: &mut Option<Box<AudioReceiver>>,
-                 audio_timer: &mut Timer)
-                 -> Result<()> {
+    /// Handles any received data, then mixes the next frame of audio from
+    /// `sources` into a packet ready to be sent by [`send_mixed`].
+    ///
+    /// Returns the length of the packet, or `None` if there is nothing to
+    /// send this frame.
+    ///
+    /// [`send_mixed`]: #method.send_mixed
+    pub fn mix(&mut self,
+               sources: &[LockedAudio],
+               receiver: &mut Option<Box<AudioReceiver>>,
+               ducking: Option<&mut Ducker>)
+               -> Result<Option<usize>> {
         let mut buffer = [0i16; 960 * 2];
         let mut mix_buffer = [0f32; 960 * 2];
-        let mut packet = [0u8; 512];
-        let mut nonce = secretbox::Nonce([0; 24]);
 
-        if let Some(receiver) = receiver.as_mut() {
-            while let Ok(status) = self.thread_items.rx.try_recv() {
-                match status {
-                    ReceiverStatus::Udp(packet) => {
-                        let mut handle = &packet[2..];
-                        let seq = handle.read_u16::<BigEndian>()?;
-                        let timestamp = handle.read_u32::<BigEndian>()?;
-                        let ssrc = handle.read_u32::<BigEndian>()?;
-
-                        nonce.0[..HEADER_LEN]
-                            .clone_from_slice(&packet[..HEADER_LEN]);
-
-                        if let Ok(mut decrypted) =
-                            secretbox::open(&packet[HEADER_LEN..], &nonce, &self.key) {
-                            let channels = opus_packet::get_nb_channels(&decrypted)?;
-
-                            let entry =
-                                self.decoder_map.entry((ssrc, channels)).or_insert_with(
-                                    || OpusDecoder::new(SAMPLE_RATE, channels).unwrap(),
-                                );
-
-                            // Strip RTP Header Extensions (one-byte)
-                            if decrypted[0] == 0xBE && decrypted[1] == 0xDE {
-                                // Read the length bytes as a big-endian u16.
-                                let header_extension_len = BigEndian::read_u16(&decrypted[2..4]);
-                                let mut offset = 4;
-                                for _ in 0..header_extension_len {
-                                    let byte = decrypted[offset];
-                                    offset += 1;
-                                    if byte == 0 {
-                                        continue;
-                                    }
-
-                                    offset += 1 + (0b1111 & (byte >> 4)) as usize;
-                                }
+        let voice_threshold = ducking.as_ref().and_then(|ducker| ducker.voice_threshold());
+        self.decode_all = voice_threshold.is_some();
 
-                                while decrypted[offset] == 0 {
-                                    offset += 1;
-                                }
+        while let Some(status) = self.thread_items.as_ref().and_then(|items| items.rx.try_recv().ok()) {
+            self.handle_status(status, receiver)?;
+        }
 
-                                decrypted = decrypted.split_off(offset);
-                            }
+        // Without threads of their own, the sockets are non-blocking and are
+        // drained here instead.
+        if self.is_polled() {
+            let mut udp_buffer = [0u8; 512];
 
-                            let len = entry.decode(&decrypted, &mut buffer, false)?;
+            while let Ok((len, _)) = self.udp.recv_from(&mut udp_buffer) {
+                self.handle_status(ReceiverStatus::Udp(udp_buffer[..len].to_vec()), receiver)?;
+            }
 
-                            let is_stereo = channels == Channels::Stereo;
+            while let Some(status) = poll_websocket(&self.client, &mut self.ws_reported) {
+                self.handle_status(status, receiver)?;
+            }
+        }
+
+        let mut heard_voice = false;
 
-                            let b = if is_stereo { len * 2 } else { len };
+        if receiver.is_some() || self.decode_all {
+            // Received audio is played out at the same pace as it is sent.
+            for (&ssrc, jitter) in &mut self.jitter_buffers {
+                match jitter.pop(&mut buffer) {
+                    Ok(Some(frame)) => {
+                        let data = &buffer[..frame.len];
 
-                            receiver
-                                .voice_packet(ssrc, seq, timestamp, is_stereo, &buffer[..b]);
+                        if let Some(threshold) = voice_threshold {
+                            heard_voice |= vad::rms(data) >= threshold;
+                        }
+
+                        if let Some(receiver) = receiver.as_mut() {
+                            if let Some(ref payload) = frame.payload {
+                                receiver.opus_packet(ssrc, frame.sequence, frame.timestamp, payload);
+                            }
+
+                            receiver.voice_packet(ssrc,
+                                                  frame.sequence,
+                                                  frame.timestamp,
+                                                  frame.stereo,
+                                                  data);
                         }
                     },
-                    ReceiverStatus::Websocket(VoiceEvent::Speaking(ev)) => {
-                        receiver.speaking_update(ev.ssrc, ev.user_id.0, ev.speaking);
-                    },
-                    ReceiverStatus::Websocket(other) => {
-                        info!("[Voice] Received other websocket data: {:?}", other);
-                    },
-                }
-            }
-        } else {
-            loop {
-                if self.thread_items.rx.try_recv().is_err() {
-                    break;
+                    Ok(None) => {},
+                    Err(why) => debug!("[Voice] Failed to decode audio from {}: {:?}", ssrc, why),
                 }
             }
         }
 
+        if let Some(receiver) = receiver.as_mut() {
+            receiver.tick();
+        }
+
+        let duck = ducking.and_then(|ducker| {
+            let voice = if voice_threshold.is_some() {
+                heard_voice
+            } else {
+                // Discord doesn't reliably say when users stop speaking, so
+                // those who have sent nothing for the hold time are dropped.
+                let hold = ducker.hold();
+
+                self.speakers.retain(|_, last| last.elapsed() < hold);
+
+                !self.speakers.is_empty()
+            };
+
+            ducker.step(voice)
+        });
+
         // Send the voice websocket keepalive if it's time
         if self.keepalive_timer.check() {
-            self.client.lock().send_json(&payload::build_keepalive())?;
+            let nonce = self.last_heartbeat.map_or(0, |(nonce, _)| nonce.wrapping_add(1));
+
+            send_json(&self.client, &payload::build_keepalive(nonce), self.is_polled())?;
+            self.last_heartbeat = Some((nonce, Instant::now()));
         }
 
         // Send UDP keepalive if it's time
@@ -308,83 +371,133 @@ impl Connection {
 
         let mut len = 0;
 
-        // Walk over all the audio files, removing those which have finished.
-        // For this purpose, we need a while loop in Rust.
-        let mut i = 0;
+        // Opus frames can only be sent untouched if nothing else needs to be
+        // mixed in alongside them.
+        let active_sources = sources
+            .iter()
+            .filter(|aud| aud.lock().playing)
+            .count();
 
-        while i < sources.len() {
-            let mut finished = false;
+        // Room left in a packet for a frame once encryption has had its share.
+        let max_passthrough = self.packet.len() - HEADER_LEN - self.crypto_mode.overhead();
 
-            let aud_lock = (&sources[i]).clone();
+        // Walk over all the audio files, reading a frame from each. Those which
+        // have finished are left in place, to be cleaned up by the voice thread
+        // once their events have fired.
+        for aud_lock in sources.iter() {
             let mut aud = aud_lock.lock();
 
+            if !aud.playing || aud.finished {
+                continue;
+            }
+
+            if !aud.apply_seek() {
+                warn!("[Voice] Seek requested on a source which can't seek; ignoring.");
+            }
+
             let vol = aud.volume;
-            let skip = !aud.playing;
+            let is_opus = match aud.source.get_type() {
+                AudioType::Opus => true,
+                AudioType::Pcm => false,
+            };
+            let duck = if aud.ducked { duck } else { None };
+            let mut passthrough = is_opus
+                && active_sources == 1
+                && (vol - 1.0).abs() < f32::EPSILON
+                && aud.filters.is_empty()
+                && duck.is_none();
+
+            // Each track is built up separately, so that its filters only
+            // affect its own audio.
+            let mut track_buffer = [0f32; 960 * 2];
+
+            // A looping track is rewound as soon as it ends, so that it
+            // restarts without a gap.
+            let mut looped = false;
+
+            let frame_len = loop {
+                let frame_len = {
+                    let aud = &mut *aud;
+                    let stream = &mut aud.source;
+
+                    // Sources are always mixed in stereo, and downmixed to mono
+                    // afterwards if need be.
+                    let source_stereo = stream.is_stereo();
+
+                    match stream.get_type() {
+                        AudioType::Opus => if passthrough {
+                            match stream.read_opus_frame() {
+                                // Too long to fit in a packet as-is, so it's
+                                // re-encoded instead.
+                                Some(ref frame) if frame.len() > max_passthrough => {
+                                    passthrough = false;
+
+                                    lazy_decoder(&mut aud.decoder).and_then(|decoder| {
+                                        decode_and_add(decoder, frame, &mut track_buffer, vol)
+                                    })
+                                },
+                                Some(frame) => {
+                                    opus_frame = frame;
+
+                                    Some(opus_frame.len())
+                                },
+                                None => None,
+                            }
+                        } else {
+                            stream.decode_and_add_opus_frame(&mut aud.decoder, &mut track_buffer, vol)
+                        },
+                        AudioType::Pcm => {
+                            let buffer_len = if source_stereo { 960 * 2 } else { 960 };
+
+                            stream.read_pcm_frame(&mut buffer[..buffer_len]).map(|len| {
+                                // Don't let a short final frame mix in stale
+                                // samples from a previous source.
+                                for value in &mut buffer[len..buffer_len] {
+                                    *value = 0;
+                                }
 
-            {
-                let stream = &mut aud.source;
+                                // May need to force interleave/copy.
+                                combine_audio(buffer, &mut track_buffer, source_stereo, vol);
 
-                if skip {
-                    i += 1;
+                                len
+                            })
+                        },
+                    }
+                };
+
+                if frame_len == Some(0) && !looped && aud.try_loop() {
+                    looped = true;
 
                     continue;
                 }
 
-                // Assume this for now, at least.
-                // We'll be fusing streams, so we can either keep
-                // as stereo or downmix to mono.
-                let is_stereo = true;
-                let source_stereo = stream.is_stereo();
-
-                if is_stereo != self.encoder_stereo {
-                    let channels = if is_stereo {
-                        Channels::Stereo
-                    } else {
-                        Channels::Mono
-                    };
-                    self.encoder = OpusEncoder::new(SAMPLE_RATE, channels, CodingMode::Audio)?;
-                    self.encoder_stereo = is_stereo;
-                }
+                break frame_len;
+            };
 
-                let temp_len = match stream.get_type() {
-                    // TODO: decode back to raw, then include.
-                    AudioType::Opus => match stream.read_opus_frame() {
-                        Some(frame) => {
-                            opus_frame = frame;
-                            opus_frame.len()
-                        },
-                        None => 0,
-                    },
-                    AudioType::Pcm => {
-                        let buffer_len = if source_stereo { 960 * 2 } else { 960 };
+            match frame_len {
+                Some(0) => aud.finished = true,
+                Some(frame_len) => {
+                    len = len.max(frame_len);
 
-                        match stream.read_pcm_frame(&mut buffer[..buffer_len]) {
-                            Some(len) => len,
-                            None => 0,
+                    if !passthrough {
+                        if !aud.apply_filters(&mut track_buffer) {
+                            aud.finished = true;
                         }
-                    },
-                };
-
-                // May need to force interleave/copy.
-                combine_audio(buffer, &mut mix_buffer, source_stereo, vol);
-
-                len = len.max(temp_len);
-                i += if temp_len > 0 {
-                    1
-                } else {
-                    sources.remove(i);
-                    finished = true;
 
-                    0
-                };
-            }
+                        if let Some((begin, end)) = duck {
+                            ramp_gain(&mut track_buffer, begin, end);
+                        }
 
-            aud.finished = finished;
+                        for (mixed, sample) in mix_buffer.iter_mut().zip(track_buffer.iter()) {
+                            *mixed += sample;
+                        }
+                    }
 
-            if !finished {
-                aud.step_frame();
+                    aud.step_frame();
+                },
+                None => aud.fail(),
             }
-        };
+        }
 
         self.soft_clip.apply(&mut mix_buffer);
 
@@ -398,25 +511,260 @@ impl Connection {
                 // Per official guidelines, send 5x silence BEFORE we stop speaking.
                 self.set_speaking(false)?;
 
-                audio_timer.await();
-
-                return Ok(());
+                return Ok(None);
             }
         } else {
             self.silence_frames = 5;
-
-            for value in &mut buffer[len..] {
-                *value = 0;
-            }
         }
 
         self.set_speaking(true)?;
 
-        let index = self.prep_packet(&mut packet, mix_buffer, &opus_frame, nonce)?;
-        audio_timer.await();
+        let mut packet = [0u8; 512];
+        let index = self.prep_packet(&mut packet, mix_buffer, &opus_frame)?;
+        self.packet = packet;
+
+        Ok(Some(index))
+    }
+
+    /// Sends the first `len` bytes of the packet built by the last call to
+    /// [`mix`].
+    ///
+    /// [`mix`]: #method.mix
+    pub fn send_mixed(&mut self, len: usize) -> Result<()> {
+        match self.udp.send_to(&self.packet[..len], self.destination) {
+            Ok(_) => {},
+            // A non-blocking socket may not have room for the packet, in
+            // which case the frame is dropped as though it were lost.
+            Err(ref why) if why.kind() == IoErrorKind::WouldBlock => {
+                debug!("[Voice] Dropped a frame; UDP socket is full");
+
+                return Ok(());
+            },
+            Err(why) => return Err(why.into()),
+        }
 
-        self.udp.send_to(&packet[..index], self.destination)?;
         self.audio_timer.reset();
+        self.stats.record_sent(len, self.sequence, self.timestamp);
+
+        Ok(())
+    }
+
+    /// Changes the settings of the encoder used for mixed audio, taking effect
+    /// from the next frame.
+    pub fn set_encoder_settings(&mut self, settings: EncoderSettings) -> Result<()> {
+        if settings == self.encoder_settings {
+            return Ok(());
+        }
+
+        if settings.needs_rebuild(&self.encoder_settings) {
+            self.encoder = settings.build()?;
+        } else {
+            settings.configure(&mut self.encoder)?;
+        }
+
+        self.encoder_settings = settings;
+
+        Ok(())
+    }
+
+    /// Statistics gathered over the lifetime of this connection.
+    pub fn stats(&self) -> &VoiceStats { &self.stats }
+
+    /// Records how the frame timer's latest tick went.
+    ///
+    /// Skipped ticks still advance the RTP timestamp, so that listeners treat
+    /// the gap as lost audio rather than playing on as if none had passed.
+    pub fn record_tick(&mut self, tick: Tick) {
+        if let Tick::Skipped(ticks) = tick {
+            self.timestamp = self.timestamp.wrapping_add(960u32.wrapping_mul(ticks));
+        }
+
+        self.stats.record_tick(tick);
+    }
+
+    /// Whether the UDP socket and websocket are polled by the connection,
+    /// rather than read from threads of their own.
+    pub fn is_polled(&self) -> bool { self.thread_items.is_none() }
+
+    /// The details used to establish this connection.
+    pub fn info(&self) -> &ConnectionInfo { &self.info }
+
+    /// Attempts to resume the voice websocket session over a fresh websocket
+    /// connection, keeping the existing UDP socket and encryption key.
+    pub fn resume(&mut self) -> Result<()> {
+        let url = generate_url(&mut self.info.endpoint)?;
+
+        let mut client = ClientBuilder::from_url(&url).connect_secure(None)?;
+        set_handshake_timeout(&mut client)?;
+        client.send_json(&payload::build_resume(&self.info))?;
+
+        let mut heartbeat_interval = None;
+        let mut resumed = false;
+
+        while heartbeat_interval.is_none() || !resumed {
+            let value = match client.recv_json()? {
+                Some(value) => value,
+                None => continue,
+            };
+
+            match VoiceEvent::deserialize(value)? {
+                VoiceEvent::Heartbeat(hello) => {
+                    heartbeat_interval = Some(hello.heartbeat_interval);
+                },
+                VoiceEvent::Resumed => resumed = true,
+                other => {
+                    debug!("[Voice] Expected hello/resumed; got: {:?}", other);
+                },
+            }
+        }
+
+        set_polling(&mut client, self.is_polled())?;
+
+        // The websocket thread shares this client, so swapping it out here
+        // switches the thread over to the new connection.
+        *self.client.lock() = client;
+        self.ws_reported = false;
+
+        // Drop anything queued up by the websocket thread from the old
+        // connection, such as reports of its failure.
+        if let Some(ref items) = self.thread_items {
+            while items.rx.try_recv().is_ok() {}
+        }
+
+        if let Some(interval) = heartbeat_interval {
+            self.keepalive_timer = Timer::new((interval as f64 * 0.75) as u64);
+        }
+
+        // Speaking state isn't carried over by a resume.
+        self.speaking = false;
+
+        info!("[Voice] Resumed connection to: {}", self.info.endpoint);
+
+        Ok(())
+    }
+
+    fn handle_status(&mut self,
+                     status: ReceiverStatus,
+                     receiver: &mut Option<Box<AudioReceiver>>)
+                     -> Result<()> {
+        match status {
+            ReceiverStatus::Udp(packet) => {
+                // Anything else arriving on the socket, such as RTCP or an
+                // echoed keepalive, is ignored rather than ending the
+                // connection.
+                if !is_rtp(&packet) {
+                    debug!("[Voice] Ignoring non-RTP packet of {} bytes", packet.len());
+
+                    return Ok(());
+                }
+
+                let seq = BigEndian::read_u16(&packet[2..4]);
+                let timestamp = BigEndian::read_u32(&packet[4..8]);
+                let ssrc = BigEndian::read_u32(&packet[8..12]);
+
+                // Only authenticated packets are counted, so that spoofed
+                // SSRCs can't fill the stats.
+                let decrypted = match self.crypto_mode.decrypt(&packet, &self.key) {
+                    Some(decrypted) => decrypted,
+                    None => {
+                        debug!("[Voice] Discarding packet from {} which failed to decrypt", ssrc);
+
+                        return Ok(());
+                    },
+                };
+
+                self.stats.record_received(ssrc, seq);
+
+                if let Some(last) = self.speakers.get_mut(&ssrc) {
+                    *last = Instant::now();
+                }
+
+                // Audio is only decoded if there's something to hand it to.
+                if receiver.is_none() && !self.decode_all {
+                    return Ok(());
+                }
+
+                let payload = match strip_header_extension(decrypted) {
+                    Some(payload) => payload,
+                    None => {
+                        debug!("[Voice] Discarding packet from {} with a bad header extension", ssrc);
+
+                        return Ok(());
+                    },
+                };
+
+                let jitter = match self.jitter_buffers.entry(ssrc) {
+                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Vacant(e) => {
+                        let jitter = opus_packet::get_nb_channels(&payload)
+                            .map_err(Error::from)
+                            .and_then(JitterBuffer::new);
+
+                        match jitter {
+                            Ok(jitter) => e.insert(jitter),
+                            Err(why) => {
+                                debug!("[Voice] Discarding bad packet from {}: {:?}", ssrc, why);
+
+                                return Ok(());
+                            },
+                        }
+                    },
+                };
+
+                if let Err(why) = jitter.push(seq, timestamp, payload) {
+                    debug!("[Voice] Discarding bad packet from {}: {:?}", ssrc, why);
+                }
+            },
+            ReceiverStatus::Websocket(VoiceEvent::HeartbeatAck(nonce)) => {
+                match self.last_heartbeat {
+                    Some((sent_nonce, sent)) if sent_nonce == nonce => {
+                        self.stats.rtt = Some(sent.elapsed());
+                    },
+                    _ => debug!("[Voice] Heartbeat ack for unknown nonce: {}", nonce),
+                }
+            },
+            ReceiverStatus::Websocket(VoiceEvent::Speaking(ev)) => {
+                // Users already in the channel when we joined are only
+                // learned of once they speak.
+                self.user_ssrcs.insert(ev.user_id, ev.ssrc);
+
+                // Soundshare alone is usually music, such as from another
+                // bot, which shouldn't keep tracks ducked.
+                if ev.speaking.intersects(SpeakingState::MICROPHONE | SpeakingState::PRIORITY) {
+                    self.speakers.insert(ev.ssrc, Instant::now());
+                } else {
+                    self.speakers.remove(&ev.ssrc);
+                }
+
+                if let Some(receiver) = receiver.as_mut() {
+                    receiver.speaking_update(ev.ssrc, ev.user_id.0, ev.speaking.is_speaking());
+                    receiver.speaking_state(ev.ssrc, ev.user_id.0, ev.speaking);
+                }
+            },
+            ReceiverStatus::Websocket(VoiceEvent::ClientConnect(ev)) => {
+                self.user_ssrcs.insert(ev.user_id, ev.audio_ssrc);
+
+                if let Some(receiver) = receiver.as_mut() {
+                    receiver.client_connect(ev.audio_ssrc, ev.user_id.0);
+                }
+            },
+            ReceiverStatus::Websocket(VoiceEvent::ClientDisconnect(ev)) => {
+                // Nothing more will arrive from the user, so their decoder can
+                // be freed.
+                if let Some(ssrc) = self.user_ssrcs.remove(&ev.user_id) {
+                    self.jitter_buffers.remove(&ssrc);
+                    self.speakers.remove(&ssrc);
+                }
+
+                if let Some(receiver) = receiver.as_mut() {
+                    receiver.client_disconnect(ev.user_id.0);
+                }
+            },
+            ReceiverStatus::Websocket(other) => {
+                info!("[Voice] Received other websocket data: {:?}", other);
+            },
+            ReceiverStatus::Disconnected(why) => return Err(why),
+        }
 
         Ok(())
     }
@@ -424,8 +772,7 @@ impl Connection {
     fn prep_packet(&mut self,
                    packet: &mut [u8; 512],
                    buffer: [f32; 1920],
-                   opus_frame: &[u8],
-                   mut nonce: Nonce)
+                   opus_frame: &[u8])
                    -> Result<usize> {
         {
             let mut cursor = &mut packet[..HEADER_LEN];
@@ -435,15 +782,16 @@ impl Connection {
             cursor.write_u32::<BigEndian>(self.ssrc)?;
         }
 
-        nonce.0[..HEADER_LEN]
-            .clone_from_slice(&packet[..HEADER_LEN]);
+        let sl_index = packet.len() - self.crypto_mode.overhead();
+        let len = if opus_frame.is_empty() {
+            let output = &mut packet[HEADER_LEN..sl_index];
+            let start = Instant::now();
 
-        let sl_index = packet.len() - 16;
-        let buffer_len = if self.encoder_stereo { 960 * 2 } else { 960 };
+            let len = self.encoder_settings.encode(&mut self.encoder, &buffer, output)?;
 
-        let len = if opus_frame.is_empty() {
-            self.encoder
-                .encode_float(&buffer[..buffer_len], &mut packet[HEADER_LEN..sl_index])?
+            self.stats.record_encode(start.elapsed());
+
+            len
         } else {
             let len = opus_frame.len();
             packet[HEADER_LEN..HEADER_LEN + len]
@@ -451,17 +799,29 @@ impl Connection {
             len
         };
 
-        let crypted = {
-            let slice = &packet[HEADER_LEN..HEADER_LEN + len];
-            secretbox::seal(slice, &nonce, &self.key)
-        };
-        let index = HEADER_LEN + crypted.len();
-        packet[HEADER_LEN..index].clone_from_slice(&crypted);
+        let index = self.crypto_mode
+            .encrypt_in_place(&mut packet[..], len, &self.key, &mut self.lite_nonce);
 
         self.sequence = self.sequence.wrapping_add(1);
         self.timestamp = self.timestamp.wrapping_add(960);
 
-        Ok(HEADER_LEN + crypted.len())
+        Ok(index)
+    }
+
+    /// Changes the flags sent while audio is being sent, updating them
+    /// straight away if audio is being sent now.
+    pub fn set_speaking_state(&mut self, state: SpeakingState) -> Result<()> {
+        if self.speaking_state == state {
+            return Ok(());
+        }
+
+        self.speaking_state = state;
+
+        if self.speaking {
+            self.send_speaking()?;
+        }
+
+        Ok(())
     }
 
     fn set_speaking(&mut self, speaking: bool) -> Result<()> {
@@ -471,21 +831,69 @@ impl Connection {
 
         self.speaking = speaking;
 
-        self.client.lock().send_json(&payload::build_speaking(speaking))
+        self.send_speaking()
+    }
+
+    fn send_speaking(&mut self) -> Result<()> {
+        let state = if self.speaking { self.speaking_state } else { SpeakingState::empty() };
+
+        send_json(&self.client, &payload::build_speaking(state, self.ssrc), self.is_polled())
     }
 }
 
 impl Drop for Connection {
     fn drop(&mut self) {
-        let _ = self.thread_items.udp_close_sender.send(0);
-        let _ = self.thread_items.ws_close_sender.send(0);
+        if let Some(ref items) = self.thread_items {
+            let _ = items.udp_close_sender.send(0);
+            let _ = items.ws_close_sender.send(0);
+        }
 
         info!("[Voice] Disconnected");
     }
 }
 
+/// Whether a received datagram is long enough to be an RTP packet, and is
+/// one.
+///
+/// RTCP packets share the version bits, but their packet types fall where
+/// an RTP packet's marker bit and payload type would be 72 to 76.
+fn is_rtp(packet: &[u8]) -> bool {
+    packet.len() >= HEADER_LEN
+        && packet[0] >> 6 == 2
+        && !(72..77).contains(&(packet[1] & 0x7f))
+}
+
+/// Removes any one-byte RTP header extensions from the start of a decrypted
+/// payload.
+///
+/// Returns `None` if the extensions run past the end of the payload.
+fn strip_header_extension(mut decrypted: Vec<u8>) -> Option<Vec<u8>> {
+    if decrypted.len() >= 4 && decrypted[0] == 0xBE && decrypted[1] == 0xDE {
+        // Read the length bytes as a big-endian u16.
+        let header_extension_len = BigEndian::read_u16(&decrypted[2..4]);
+        let mut offset = 4;
+        for _ in 0..header_extension_len {
+            let byte = *decrypted.get(offset)?;
+            offset += 1;
+            if byte == 0 {
+                continue;
+            }
+
+            offset += 1 + (0b1111 & (byte >> 4)) as usize;
+        }
+
+        while *decrypted.get(offset)? == 0 {
+            offset += 1;
+        }
+
+        decrypted = decrypted.split_off(offset);
+    }
+
+    Some(decrypted)
+}
+
 #[inline]
-fn combine_audio(
+pub(crate) fn combine_audio(
     raw_buffer: [i16; 1920],
     float_buffer: &mut [f32; 1920],
     true_stereo: bool,
@@ -511,7 +919,7 @@ fn generate_url(endpoint: &mut String) -> Result<WebsocketUrl> {
 }
 
 #[inline]
-fn encryption_key(client: &mut Client) -> Result<Key> {
+fn encryption_key(client: &mut Client, mode: CryptoMode) -> Result<Key> {
     loop {
         let value = match client.recv_json()? {
             Some(value) => value,
@@ -520,7 +928,7 @@ fn encryption_key(client: &mut Client) -> Result<Key> {
 
         match VoiceEvent::deserialize(value)? {
             VoiceEvent::Ready(ready) => {
-                if ready.mode != CRYPTO_MODE {
+                if ready.mode != mode.name() {
                     return Err(Error::Voice(VoiceError::VoiceModeInvalid));
                 }
 
@@ -539,14 +947,67 @@ fn encryption_key(client: &mut Client) -> Result<Key> {
     }
 }
 
-#[inline]
-fn has_valid_mode<T, It> (modes: It) -> bool
-where T: for<'a> PartialEq<&'a str>,
-      It : IntoIterator<Item=T>
-{
-    modes.into_iter().any(|s| s == CRYPTO_MODE)
+/// Whether an error from the websocket means that the connection has dropped,
+/// rather than that no message has arrived within the read timeout.
+fn is_disconnect(why: &Error) -> bool {
+    match *why {
+        Error::WebSocket(WebSocketError::IoError(ref e)) => {
+            e.kind() != IoErrorKind::WouldBlock && e.kind() != IoErrorKind::TimedOut
+        },
+        Error::WebSocket(_) | Error::Gateway(GatewayError::Closed(_)) => true,
+        _ => false,
+    }
 }
 
+/// Bounds how long the handshake may wait on any single message.
+fn set_handshake_timeout(client: &mut Client) -> Result<()> {
+    client
+        .stream_ref()
+        .as_tcp()
+        .set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    Ok(())
+}
+
+/// Lets the websocket be polled for messages without holding the client
+/// locked for long.
+///
+/// A websocket polled by the connection itself is made non-blocking, as it is
+/// read from on a mixer thread shared with other connections. Writes are
+/// made blocking while they last, bounded by a timeout.
+fn set_polling(client: &mut Client, polled: bool) -> Result<()> {
+    let stream = client.stream_ref().as_tcp();
+
+    if polled {
+        stream.set_write_timeout(Some(Duration::from_millis(250)))?;
+        stream.set_nonblocking(true)?;
+    } else {
+        stream.set_read_timeout(Some(Duration::from_millis(25)))?;
+    }
+
+    Ok(())
+}
+
+/// Sends a message over the websocket.
+///
+/// A non-blocking websocket is made blocking for the duration of the write,
+/// as a write which would block could otherwise leave part of a message
+/// unsent and the stream out of step.
+fn send_json(client: &Mutex<Client>, value: &Value, polled: bool) -> Result<()> {
+    let mut client = client.lock();
+
+    if !polled {
+        return client.send_json(value);
+    }
+
+    client.stream_ref().as_tcp().set_nonblocking(false)?;
+    let result = client.send_json(value);
+    client.stream_ref().as_tcp().set_nonblocking(true)?;
+
+    result
+}
+
+/// Starts the threads which receive from the websocket and UDP socket.
 #[inline]
 fn start_threads(client: Arc<Mutex<Client>>, udp: &UdpSocket) -> Result<ThreadItems> {
     let (udp_close_sender, udp_close_reader) = mpsc::channel();
@@ -582,27 +1043,22 @@ fn start_threads(client: Arc<Mutex<Client>>, udp: &UdpSocket) -> Result<ThreadIt
 
     let ws_thread = ThreadBuilder::new()
         .name(format!("{} WS", thread_name))
-        .spawn(move || loop {
-            while let Ok(Some(value)) = client.lock().recv_json() {
-                let msg = match VoiceEvent::deserialize(value) {
-                    Ok(msg) => msg,
-                    Err(why) => {
-                        warn!("Error deserializing voice event: {:?}", why);
-
-                        break;
-                    },
-                };
+        .spawn(move || {
+            let mut reported = false;
+
+            loop {
+                while let Some(status) = poll_websocket(&client, &mut reported) {
+                    if tx_clone.send(status).is_err() {
+                        return;
+                    }
+                }
 
-                if tx_clone.send(ReceiverStatus::Websocket(msg)).is_err() {
+                if ws_close_reader.try_recv().is_ok() {
                     return;
                 }
-            }
 
-            if ws_close_reader.try_recv().is_ok() {
-                return;
+                thread::sleep(Duration::from_millis(25));
             }
-
-            thread::sleep(Duration::from_millis(25));
         })?;
 
     Ok(ThreadItems {
@@ -613,3 +1069,34 @@ fn start_threads(client: Arc<Mutex<Client>>, udp: &UdpSocket) -> Result<ThreadIt
         ws_thread,
     })
 }
+
+/// Reads the next event from the websocket, if one has arrived.
+///
+/// A dropped connection is only reported once, until the client is replaced
+/// with one which works.
+fn poll_websocket(client: &Mutex<Client>, reported: &mut bool) -> Option<ReceiverStatus> {
+    let value = match client.lock().recv_json() {
+        Ok(Some(value)) => value,
+        Ok(None) => return None,
+        Err(why) => {
+            if is_disconnect(&why) && !*reported {
+                *reported = true;
+
+                return Some(ReceiverStatus::Disconnected(why));
+            }
+
+            return None;
+        },
+    };
+
+    *reported = false;
+
+    match VoiceEvent::deserialize(value) {
+        Ok(msg) => Some(ReceiverStatus::Websocket(msg)),
+        Err(why) => {
+            warn!("Error deserializing voice event: {:?}", why);
+
+            None
+        },
+    }
+}
@@ -0,0 +1,149 @@
+use byteorder::{BigEndian, ByteOrder};
+use sodiumoxide::crypto::secretbox::{self, Key, Nonce, MACBYTES, NONCEBYTES};
+use super::audio::HEADER_LEN;
+
+/// The encryption modes supported for voice packets, which differ in how the
+/// nonce for each packet is chosen.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum CryptoMode {
+    /// The RTP header is used as the nonce.
+    Normal,
+    /// A random 24-byte nonce is appended to each packet.
+    Suffix,
+    /// An incrementing 4-byte nonce is appended to each packet.
+    Lite,
+}
+
+/// Supported modes, from most to least preferred.
+const PREFERENCE: [CryptoMode; 3] = [CryptoMode::Lite, CryptoMode::Suffix, CryptoMode::Normal];
+
+impl CryptoMode {
+    /// The name of this mode, as used by the voice gateway.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            CryptoMode::Normal => "xsalsa20_poly1305",
+            CryptoMode::Suffix => "xsalsa20_poly1305_suffix",
+            CryptoMode::Lite => "xsalsa20_poly1305_lite",
+        }
+    }
+
+    /// Picks the most preferred mode out of those offered by the voice server.
+    pub(crate) fn negotiate<T, It>(modes: It) -> Option<CryptoMode>
+        where T: AsRef<str>, It: IntoIterator<Item=T> {
+        let offered = modes.into_iter().collect::<Vec<T>>();
+
+        PREFERENCE
+            .iter()
+            .find(|mode| offered.iter().any(|name| name.as_ref() == mode.name()))
+            .cloned()
+    }
+
+    /// Number of nonce bytes appended to the end of each packet.
+    pub(crate) fn nonce_size(self) -> usize {
+        match self {
+            CryptoMode::Normal => 0,
+            CryptoMode::Suffix => NONCEBYTES,
+            CryptoMode::Lite => 4,
+        }
+    }
+
+    /// Number of bytes added to a payload by encryption.
+    pub(crate) fn overhead(self) -> usize { MACBYTES + self.nonce_size() }
+
+    /// Encrypts the `payload_len` bytes following the RTP header of `packet` in
+    /// place, appending any nonce.
+    ///
+    /// `lite_nonce` is the counter used by [`CryptoMode::Lite`], and is
+    /// incremented if used.
+    ///
+    /// Returns the length of the complete packet.
+    ///
+    /// [`CryptoMode::Lite`]: #variant.Lite
+    pub(crate) fn encrypt_in_place(self,
+                                   packet: &mut [u8],
+                                   payload_len: usize,
+                                   key: &Key,
+                                   lite_nonce: &mut u32)
+                                   -> usize {
+        let mut nonce = Nonce([0; NONCEBYTES]);
+
+        match self {
+            CryptoMode::Normal => nonce.0[..HEADER_LEN].clone_from_slice(&packet[..HEADER_LEN]),
+            CryptoMode::Suffix => nonce = secretbox::gen_nonce(),
+            CryptoMode::Lite => {
+                BigEndian::write_u32(&mut nonce.0[..4], *lite_nonce);
+                *lite_nonce = lite_nonce.wrapping_add(1);
+            },
+        }
+
+        let crypted = secretbox::seal(&packet[HEADER_LEN..HEADER_LEN + payload_len], &nonce, key);
+        let mut index = HEADER_LEN + crypted.len();
+        packet[HEADER_LEN..index].clone_from_slice(&crypted);
+
+        let nonce_size = self.nonce_size();
+        packet[index..index + nonce_size].clone_from_slice(&nonce.0[..nonce_size]);
+        index += nonce_size;
+
+        index
+    }
+
+    /// Decrypts the payload of a received RTP packet.
+    ///
+    /// Returns `None` if the packet is malformed or fails authentication.
+    pub(crate) fn decrypt(self, packet: &[u8], key: &Key) -> Option<Vec<u8>> {
+        let nonce_size = self.nonce_size();
+
+        if packet.len() < HEADER_LEN + MACBYTES + nonce_size {
+            return None;
+        }
+
+        let body_end = packet.len() - nonce_size;
+        let mut nonce = Nonce([0; NONCEBYTES]);
+
+        match self {
+            CryptoMode::Normal => nonce.0[..HEADER_LEN].clone_from_slice(&packet[..HEADER_LEN]),
+            CryptoMode::Suffix | CryptoMode::Lite => {
+                nonce.0[..nonce_size].clone_from_slice(&packet[body_end..]);
+            },
+        }
+
+        secretbox::open(&packet[HEADER_LEN..body_end], &nonce, key).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sodiumoxide::{self, crypto::secretbox};
+    use super::*;
+
+    fn round_trip(mode: CryptoMode) {
+        assert!(sodiumoxide::init());
+
+        let key = secretbox::gen_key();
+        let payload = [0xf8, 0xff, 0xfe, 0x01, 0x02];
+        let mut lite_nonce = 7;
+
+        let mut packet = [0u8; 512];
+        packet[..HEADER_LEN].clone_from_slice(&[0x80, 0x78, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]);
+        packet[HEADER_LEN..HEADER_LEN + payload.len()].clone_from_slice(&payload);
+
+        let len = mode.encrypt_in_place(&mut packet, payload.len(), &key, &mut lite_nonce);
+        assert_eq!(len, HEADER_LEN + payload.len() + mode.overhead());
+        assert_eq!(lite_nonce, if mode == CryptoMode::Lite { 8 } else { 7 });
+
+        assert_eq!(mode.decrypt(&packet[..len], &key), Some(payload.to_vec()));
+
+        // Tampering with the ciphertext fails authentication.
+        packet[HEADER_LEN] ^= 1;
+        assert_eq!(mode.decrypt(&packet[..len], &key), None);
+    }
+
+    #[test]
+    fn normal_round_trip() { round_trip(CryptoMode::Normal); }
+
+    #[test]
+    fn suffix_round_trip() { round_trip(CryptoMode::Suffix); }
+
+    #[test]
+    fn lite_round_trip() { round_trip(CryptoMode::Lite); }
+}
@@ -1,14 +1,333 @@
-#[derive(Debug, Deserialize)]
+use byteorder::{LittleEndian, WriteBytesExt};
+use internal::prelude::*;
+use opus::{Application as CodingMode, Bitrate, Decoder as OpusDecoder};
+use serde_json;
+use std::io::Write;
+use super::audio::SAMPLE_RATE;
+use super::connection::combine_audio;
+use super::metadata::AudioMetadata;
+use super::{AudioSource, AudioType, EncoderSettings, VoiceError};
+
+/// The version of the DCA format written by [`DcaWriter`].
+///
+/// [`DcaWriter`]: struct.DcaWriter.html
+const DCA_VERSION: u64 = 1;
+
+/// The metadata block of a DCA1 file.
+///
+/// Only the `opus` section is required when reading a file. All other
+/// sections are optional, as are most of their fields.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct DcaMetadata {
-    opus: OpusInfo,
+    /// Information about the tool which created the file.
+    #[serde(default)]
+    pub dca: Option<DcaFormat>,
+    /// How the audio was encoded.
+    pub opus: DcaOpus,
+    /// Information about the track itself.
+    #[serde(default)]
+    pub info: Option<DcaTrackInfo>,
+    /// Information about the audio the file was created from.
+    #[serde(default)]
+    pub origin: Option<DcaOrigin>,
+    /// Any other data stored by the creating tool.
+    #[serde(default)]
+    pub extra: Option<Value>,
+}
+
+/// The `dca` section of a DCA file's metadata.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DcaFormat {
+    /// The version of the format.
+    #[serde(default)]
+    pub version: Option<u64>,
+    /// The tool which created the file.
+    #[serde(default)]
+    pub tool: Option<DcaTool>,
+}
+
+/// The tool which created a DCA file.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DcaTool {
+    /// The name of the tool.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The version of the tool.
+    #[serde(default)]
+    pub version: Option<String>,
+    /// A URL to the tool's homepage.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// The author of the tool.
+    #[serde(default)]
+    pub author: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct OpusInfo {
-    /// Number of channels
-    channels: u8,
+/// The `opus` section of a DCA file's metadata.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DcaOpus {
+    /// What the encoder was tuned for: one of `"voip"`, `"audio"` or
+    /// `"lowdelay"`.
+    #[serde(default)]
+    pub mode: Option<String>,
+    /// The sample rate of the encoded audio, in Hz.
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    /// The number of samples per channel in each frame.
+    #[serde(default)]
+    pub frame_size: Option<u32>,
+    /// The target bitrate, in bits per second, if one was set.
+    #[serde(default)]
+    pub abr: Option<u32>,
+    /// Whether the bitrate was allowed to vary between frames.
+    #[serde(default)]
+    pub vbr: Option<bool>,
+    /// The number of channels.
+    pub channels: u8,
+}
+
+/// The `info` section of a DCA file's metadata.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DcaTrackInfo {
+    /// The title of the track.
+    #[serde(default)]
+    pub title: Option<String>,
+    /// The artist of the track.
+    #[serde(default)]
+    pub artist: Option<String>,
+    /// The album the track is from.
+    #[serde(default)]
+    pub album: Option<String>,
+    /// The genre of the track.
+    #[serde(default)]
+    pub genre: Option<String>,
+    /// Any comments attached to the track.
+    #[serde(default)]
+    pub comments: Option<String>,
+    /// The track's cover art, as base64-encoded image data.
+    #[serde(default)]
+    pub cover: Option<String>,
+}
+
+/// The `origin` section of a DCA file's metadata.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DcaOrigin {
+    /// The kind of input the file was created from, such as `"file"`.
+    #[serde(default)]
+    pub source: Option<String>,
+    /// The bitrate of the original audio, in bits per second.
+    #[serde(default)]
+    pub abr: Option<u32>,
+    /// The number of channels in the original audio.
+    #[serde(default)]
+    pub channels: Option<u8>,
+    /// The encoding of the original audio.
+    #[serde(default)]
+    pub encoding: Option<String>,
+    /// Where the original audio was found.
+    #[serde(default)]
+    pub url: Option<String>,
 }
 
 impl DcaMetadata {
     pub fn is_stereo(&self) -> bool { self.opus.channels == 2 }
+
+    /// The parts of the metadata which describe the track.
+    pub(crate) fn audio_metadata(&self) -> AudioMetadata {
+        let info = self.info.clone().unwrap_or_default();
+        let origin = self.origin.clone().unwrap_or_default();
+
+        AudioMetadata {
+            title: info.title,
+            artist: info.artist,
+            sample_rate: self.opus.sample_rate,
+            channels: Some(self.opus.channels),
+            source_url: origin.url,
+            ..Default::default()
+        }
+    }
+}
+
+/// Encodes an audio source to Opus and writes it out as a DCA1 file, which
+/// can later be played with [`dca`] without any transcoding.
+///
+/// Audio is encoded with the same [`EncoderSettings`] used to send mixed
+/// audio. Opus sources are decoded and encoded again, so that every file is
+/// written with the chosen settings.
+///
+/// The `info` and `origin` sections are filled in from the source's
+/// [`metadata`] unless they are set explicitly.
+///
+/// # Examples
+///
+/// Transcoding a file ahead of time:
+///
+/// ```rust,ignore
+/// use serenity::voice::{self, DcaTrackInfo, DcaWriter};
+/// use std::fs::File;
+///
+/// let mut source = voice::ffmpeg("song.flac")?;
+///
+/// DcaWriter::new()
+///     .info(DcaTrackInfo {
+///         album: Some("Greatest Hits".to_string()),
+///         ..Default::default()
+///     })
+///     .write(&mut *source, File::create("song.dca")?)?;
+/// ```
+///
+/// [`EncoderSettings`]: struct.EncoderSettings.html
+/// [`dca`]: fn.dca.html
+/// [`metadata`]: trait.AudioSource.html#method.metadata
+#[derive(Clone, Debug, Default)]
+pub struct DcaWriter {
+    encoder_settings: EncoderSettings,
+    extra: Option<Value>,
+    info: Option<DcaTrackInfo>,
+    origin: Option<DcaOrigin>,
+}
+
+impl DcaWriter {
+    /// Creates a writer using the default [`EncoderSettings`].
+    ///
+    /// [`EncoderSettings`]: struct.EncoderSettings.html
+    pub fn new() -> Self { Self::default() }
+
+    /// Sets the settings used to encode the audio.
+    pub fn encoder_settings(mut self, settings: EncoderSettings) -> Self {
+        self.encoder_settings = settings;
+
+        self
+    }
+
+    /// Sets the `info` section, in place of the one made from the source's
+    /// metadata.
+    pub fn info(mut self, info: DcaTrackInfo) -> Self {
+        self.info = Some(info);
+
+        self
+    }
+
+    /// Sets the `origin` section, in place of the one made from the source's
+    /// metadata.
+    pub fn origin(mut self, origin: DcaOrigin) -> Self {
+        self.origin = Some(origin);
+
+        self
+    }
+
+    /// Sets the `extra` section.
+    pub fn extra(mut self, extra: Value) -> Self {
+        self.extra = Some(extra);
+
+        self
+    }
+
+    /// The metadata which will be written for the given source.
+    pub fn metadata(&self, source: &AudioSource) -> DcaMetadata {
+        let settings = &self.encoder_settings;
+        let audio = source.metadata();
+
+        let mode = match settings.application {
+            CodingMode::Voip => "voip",
+            CodingMode::Audio => "audio",
+            CodingMode::LowDelay => "lowdelay",
+        };
+
+        let abr = match settings.bitrate {
+            Bitrate::Bits(bits) => Some(bits as u32),
+            Bitrate::Auto | Bitrate::Max => None,
+        };
+
+        let info = self.info.clone().unwrap_or_else(|| DcaTrackInfo {
+            title: audio.title.clone(),
+            artist: audio.artist.clone(),
+            ..Default::default()
+        });
+
+        let origin = self.origin.clone().unwrap_or_else(|| DcaOrigin {
+            channels: audio.channels,
+            url: audio.source_url.clone(),
+            ..Default::default()
+        });
+
+        DcaMetadata {
+            dca: Some(DcaFormat {
+                version: Some(DCA_VERSION),
+                tool: Some(DcaTool {
+                    name: Some("serenity".to_string()),
+                    version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                    url: Some("https://github.com/serenity-rs/serenity".to_string()),
+                    author: None,
+                }),
+            }),
+            opus: DcaOpus {
+                mode: Some(mode.to_string()),
+                sample_rate: Some(SAMPLE_RATE),
+                frame_size: Some(960),
+                abr,
+                vbr: Some(settings.vbr),
+                channels: if settings.stereo { 2 } else { 1 },
+            },
+            info: Some(info),
+            origin: Some(origin),
+            extra: self.extra.clone(),
+        }
+    }
+
+    /// Reads the source to its end, writing it out as a DCA1 file.
+    ///
+    /// Returns the metadata which was written.
+    pub fn write<W: Write>(&self, source: &mut AudioSource, mut writer: W) -> Result<DcaMetadata> {
+        let metadata = self.metadata(source);
+        let json = serde_json::to_vec(&metadata)?;
+
+        writer.write_all(b"DCA1")?;
+        writer.write_i32::<LittleEndian>(json.len() as i32)?;
+        writer.write_all(&json)?;
+
+        let mut decoder = None;
+        let mut encoder = self.encoder_settings.build()?;
+        let mut output = [0u8; 4000];
+
+        while let Some(frame) = read_frame(source, &mut decoder)? {
+            let len = self.encoder_settings.encode(&mut encoder, &frame, &mut output)?;
+
+            writer.write_i16::<LittleEndian>(len as i16)?;
+            writer.write_all(&output[..len])?;
+        }
+
+        writer.flush()?;
+
+        Ok(metadata)
+    }
+}
+
+/// Reads the next frame of a source as interleaved stereo audio, padding a
+/// short final frame with silence.
+///
+/// Returns `None` at the end of the stream.
+fn read_frame(source: &mut AudioSource, decoder: &mut Option<OpusDecoder>) -> Result<Option<[f32; 1920]>> {
+    let mut frame = [0f32; 1920];
+
+    let len = match source.get_type() {
+        AudioType::Opus => source.decode_and_add_opus_frame(decoder, &mut frame, 1.0),
+        AudioType::Pcm => {
+            let stereo = source.is_stereo();
+            let mut buffer = [0i16; 1920];
+            let samples = if stereo { 1920 } else { 960 };
+
+            let len = source.read_pcm_frame(&mut buffer[..samples]);
+
+            combine_audio(buffer, &mut frame, stereo, 1.0);
+
+            len
+        },
+    };
+
+    match len {
+        Some(0) => Ok(None),
+        Some(_) => Ok(Some(frame)),
+        None => Err(Error::Voice(VoiceError::SourceRead)),
+    }
 }
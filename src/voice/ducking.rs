@@ -0,0 +1,151 @@
+use std::time::{Duration, Instant};
+use super::audio::duration_to_frames;
+
+/// What causes tracks to be ducked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuckingTrigger {
+    /// Duck while Discord reports that any other user is speaking, through
+    /// their microphone or as a priority speaker. Users only sharing sound,
+    /// such as other music bots, are not counted.
+    ///
+    /// As Discord doesn't always report when a user stops speaking, a user
+    /// also stops counting once no audio has been received from them for the
+    /// [`hold`] time.
+    ///
+    /// [`hold`]: struct.DuckingSettings.html#structfield.hold
+    Speaking,
+    /// Duck while the audio received from any other user is louder than the
+    /// given RMS level, as a fraction of full scale.
+    ///
+    /// This reacts to what is actually heard, rather than Discord's speaking
+    /// flag, at the cost of decoding all received audio.
+    VoiceActivity(f32),
+}
+
+/// Settings for automatically lowering the volume of tracks while other users
+/// talk, set through [`Handler::set_ducking`].
+///
+/// Only tracks marked with [`Audio::duck`] are affected.
+///
+/// # Examples
+///
+/// Dipping background music to a fifth of its volume:
+///
+/// ```rust,ignore
+/// use serenity::voice::DuckingSettings;
+///
+/// handler.set_ducking(Some(DuckingSettings {
+///     gain: 0.2,
+///     ..Default::default()
+/// }));
+///
+/// let music = handler.play_returning(source);
+/// music.lock().duck(true);
+/// ```
+///
+/// [`Audio::duck`]: struct.Audio.html#method.duck
+/// [`Handler::set_ducking`]: struct.Handler.html#method.set_ducking
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DuckingSettings {
+    /// What causes tracks to be ducked.
+    ///
+    /// Defaults to [`DuckingTrigger::Speaking`].
+    ///
+    /// [`DuckingTrigger::Speaking`]: enum.DuckingTrigger.html#variant.Speaking
+    pub trigger: DuckingTrigger,
+    /// The gain applied to ducked tracks, on top of their volume.
+    ///
+    /// Defaults to `0.3`.
+    pub gain: f32,
+    /// How long the gain takes to change when ducking or restoring.
+    ///
+    /// Defaults to 200ms.
+    pub fade: Duration,
+    /// How long to wait after the last user stops talking before restoring
+    /// the gain.
+    ///
+    /// Defaults to 1 second.
+    pub hold: Duration,
+}
+
+impl Default for DuckingSettings {
+    fn default() -> Self {
+        Self {
+            trigger: DuckingTrigger::Speaking,
+            gain: 0.3,
+            fade: Duration::from_millis(200),
+            hold: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The current state of ducking for a connection.
+pub(crate) struct Ducker {
+    gain: f32,
+    last_voice: Option<Instant>,
+    settings: DuckingSettings,
+}
+
+impl Ducker {
+    pub(crate) fn new(settings: DuckingSettings) -> Self {
+        Self {
+            gain: 1.0,
+            last_voice: None,
+            settings,
+        }
+    }
+
+    /// Changes the settings, keeping the current gain so that it moves
+    /// smoothly to its new target.
+    pub(crate) fn set_settings(&mut self, settings: DuckingSettings) { self.settings = settings; }
+
+    /// How long ducking is held after the last user stops talking.
+    pub(crate) fn hold(&self) -> Duration { self.settings.hold }
+
+    /// The RMS level above which received audio counts as voice activity, if
+    /// received audio needs to be checked at all.
+    pub(crate) fn voice_threshold(&self) -> Option<f32> {
+        match self.settings.trigger {
+            DuckingTrigger::VoiceActivity(threshold) => Some(threshold),
+            DuckingTrigger::Speaking => None,
+        }
+    }
+
+    /// Moves the gain one frame closer to its target, given whether anyone
+    /// was talking during the frame.
+    ///
+    /// Returns the gain at the start and end of the frame, or `None` if ducked
+    /// tracks are unaffected.
+    pub(crate) fn step(&mut self, voice: bool) -> Option<(f32, f32)> {
+        let now = Instant::now();
+
+        if voice {
+            self.last_voice = Some(now);
+        }
+
+        let ducked = match self.last_voice {
+            Some(at) => now.duration_since(at) < self.settings.hold,
+            None => false,
+        };
+        let target = if ducked { self.settings.gain } else { 1.0 };
+
+        let frames = duration_to_frames(self.settings.fade).max(1) as f32;
+        let step = (1.0 - self.settings.gain).abs() / frames;
+
+        let begin = self.gain;
+
+        self.gain = if (target - begin).abs() <= step {
+            target
+        } else if target < begin {
+            begin - step
+        } else {
+            begin + step
+        };
+
+        if (begin - 1.0).abs() < ::std::f32::EPSILON && (self.gain - 1.0).abs() < ::std::f32::EPSILON {
+            None
+        } else {
+            Some((begin, self.gain))
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use internal::prelude::*;
+use opus::{
+    Application as CodingMode,
+    Bitrate,
+    Channels,
+    Encoder as OpusEncoder,
+};
+use super::audio::SAMPLE_RATE;
+
+/// Settings for the Opus encoder used to send mixed audio.
+///
+/// These have no effect on Opus sources which are played alone at full volume,
+/// as their frames are sent without being re-encoded.
+///
+/// # Examples
+///
+/// Encoding music at 128kbps:
+///
+/// ```rust,ignore
+/// use serenity::voice::{Bitrate, EncoderSettings};
+///
+/// handler.set_encoder_settings(EncoderSettings {
+///     bitrate: Bitrate::Bits(128_000),
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EncoderSettings {
+    /// What the encoder should be tuned for.
+    ///
+    /// Defaults to [`CodingMode::Audio`].
+    ///
+    /// [`CodingMode::Audio`]: enum.CodingMode.html#variant.Audio
+    pub application: CodingMode,
+    /// The target bitrate.
+    ///
+    /// Defaults to [`Bitrate::Auto`].
+    ///
+    /// [`Bitrate::Auto`]: enum.Bitrate.html#variant.Auto
+    pub bitrate: Bitrate,
+    /// Whether to include in-band forward error correction data, allowing
+    /// listeners to recover from lost packets.
+    ///
+    /// Defaults to `false`.
+    pub fec: bool,
+    /// The expected packet loss, as a percentage from `0` to `100`. Higher
+    /// values make forward error correction more robust, at the cost of
+    /// quality.
+    ///
+    /// Defaults to `0`.
+    pub packet_loss_percent: u8,
+    /// Whether to send stereo audio, rather than downmixing to mono.
+    ///
+    /// Defaults to `true`.
+    pub stereo: bool,
+    /// Whether the bitrate may vary from frame to frame, rather than being
+    /// held constant.
+    ///
+    /// Defaults to `true`.
+    pub vbr: bool,
+}
+
+impl EncoderSettings {
+    /// Creates an encoder with these settings.
+    pub(crate) fn build(&self) -> Result<OpusEncoder> {
+        let channels = if self.stereo { Channels::Stereo } else { Channels::Mono };
+        let mut encoder = OpusEncoder::new(SAMPLE_RATE, channels, self.application)?;
+
+        self.configure(&mut encoder)?;
+
+        Ok(encoder)
+    }
+
+    /// Whether changing from these settings to `other` requires a new encoder,
+    /// rather than just reconfiguring the existing one.
+    pub(crate) fn needs_rebuild(&self, other: &EncoderSettings) -> bool {
+        self.application != other.application || self.stereo != other.stereo
+    }
+
+    /// Encodes a frame of interleaved stereo audio with an encoder built from
+    /// these settings, downmixing it to mono first if need be.
+    pub(crate) fn encode(&self,
+                         encoder: &mut OpusEncoder,
+                         frame: &[f32; 1920],
+                         output: &mut [u8])
+                         -> Result<usize> {
+        if self.stereo {
+            return Ok(encoder.encode_float(frame, output)?);
+        }
+
+        let mut mono = [0f32; 960];
+
+        for (sample, pair) in mono.iter_mut().zip(frame.chunks(2)) {
+            *sample = (pair[0] + pair[1]) / 2.0;
+        }
+
+        Ok(encoder.encode_float(&mono, output)?)
+    }
+
+    /// Applies the settings which may be changed on a live encoder.
+    pub(crate) fn configure(&self, encoder: &mut OpusEncoder) -> Result<()> {
+        encoder.set_bitrate(self.bitrate)?;
+        encoder.set_inband_fec(self.fec)?;
+        encoder.set_packet_loss_perc(i32::from(self.packet_loss_percent.min(100)))?;
+        encoder.set_vbr(self.vbr)?;
+
+        Ok(())
+    }
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        Self {
+            application: CodingMode::Audio,
+            bitrate: Bitrate::Auto,
+            fec: false,
+            packet_loss_percent: 0,
+            stereo: true,
+            vbr: true,
+        }
+    }
+}
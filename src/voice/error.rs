@@ -1,23 +1,43 @@
 use serde_json::{Error as JsonError, Value};
 use std::{
-    io::Error as IoError,
-    process::Output
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    process::{ExitStatus, Output}
 };
 
 /// An error returned from the voice module.
 // Errors which are not visible to the end user are hidden.
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum VoiceError {
+    /// The voice server could not be connected to, for a reason other than
+    /// those covered by other variants, such as a network error.
+    ///
+    /// A description of the underlying error is given.
+    Connect(String),
     /// An indicator that an endpoint URL was invalid.
     EndpointUrl,
     #[doc(hidden)] ExpectedHandshake,
     #[doc(hidden)] FindingByte,
     #[doc(hidden)] HostnameResolve,
     #[doc(hidden)] KeyGen,
+    /// A child process feeding an audio source, such as `ffmpeg`, exited
+    /// unsuccessfully.
+    ///
+    /// The exit status and the last lines written to stderr are given.
+    ProcessFailed(ExitStatus, String),
+    /// An audio source failed while being read in full, such as when creating
+    /// a [`CachedAudio`] or writing it out with a [`DcaWriter`].
+    ///
+    /// [`CachedAudio`]: struct.CachedAudio.html
+    /// [`DcaWriter`]: struct.DcaWriter.html
+    SourceRead,
     /// An error occurred while checking if a path is stereo.
     Streams,
+    /// A connection was not established in time.
+    Timeout,
     #[doc(hidden)] VoiceModeInvalid,
-    #[doc(hidden)] VoiceModeUnavailable,
+    /// None of the encryption modes offered by the voice server are
+    /// supported.
+    VoiceModeUnavailable,
     /// An error occurred while running `youtube-dl`.
     YouTubeDLRun(Output),
     /// An error occurred while processing the JSON output from `youtube-dl`.
@@ -38,3 +58,41 @@ pub enum DcaError {
     InvalidMetadata(JsonError),
     InvalidSize(i32),
 }
+
+/// An error returned from the ogg method.
+#[derive(Debug)]
+pub enum OggError {
+    IoError(IoError),
+    /// The file is not a valid Ogg container.
+    InvalidData(IoError),
+    /// The file does not contain an Opus stream.
+    NotOpus,
+    /// The Opus stream has more than two channels.
+    UnsupportedChannels(u8),
+    /// The Opus stream is not made up of 20ms frames, and so can't be sent
+    /// without transcoding.
+    UnsupportedFrameSize(usize),
+}
+
+impl OggError {
+    pub(crate) fn from_io(why: IoError) -> Self {
+        if why.kind() == IoErrorKind::InvalidData {
+            OggError::InvalidData(why)
+        } else {
+            OggError::IoError(why)
+        }
+    }
+}
+
+/// An error returned from the wav method.
+#[derive(Debug)]
+pub enum WavError {
+    IoError(IoError),
+    /// The file is not a valid WAV file.
+    InvalidHeader,
+    /// The file has more than two channels.
+    UnsupportedChannels(u16),
+    /// The file's samples are stored in an unsupported format, given by its
+    /// format tag and bits per sample.
+    UnsupportedFormat(u16, u16),
+}
@@ -0,0 +1,180 @@
+use internal::nanos;
+use std::{
+    mem,
+    time::Duration
+};
+use super::{Audio, LockedAudio};
+
+/// A reason for which a track raised an error event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TrackError {
+    /// The underlying source failed to produce a frame, and playback of the
+    /// track was stopped.
+    Read,
+    /// A seek was requested, but the underlying source was unable to change
+    /// its position. Playback continues from the current position.
+    Seek,
+}
+
+type EndCallback = Box<FnMut(&mut Audio) + Send>;
+type ErrorCallback = Box<FnMut(&mut Audio, TrackError) + Send>;
+type PositionCallback = Box<FnMut(&mut Audio) + Send>;
+
+struct Periodic {
+    next: Duration,
+    period: Duration,
+    callback: PositionCallback,
+}
+
+impl Periodic {
+    /// Moves the next tick to the first one after `position`.
+    fn skip_to(&mut self, position: Duration) {
+        let period = nanos(self.period);
+
+        if period == 0 {
+            return;
+        }
+
+        let ticks = nanos(position) / period + 1;
+
+        self.next = self.period * ticks as u32;
+    }
+}
+
+struct Position {
+    position: Duration,
+    /// Whether the callback has run since playback last moved back before
+    /// `position`.
+    fired: bool,
+    callback: PositionCallback,
+}
+
+/// Callbacks registered on a single [`Audio`], along with events which have
+/// been raised but not yet dispatched.
+///
+/// [`Audio`]: struct.Audio.html
+#[derive(Default)]
+pub(crate) struct EventStore {
+    end: Vec<EndCallback>,
+    error: Vec<ErrorCallback>,
+    positions: Vec<Position>,
+    periodic: Vec<Periodic>,
+    raised_errors: Vec<TrackError>,
+    /// The position of the track when events were last dispatched, used to
+    /// notice when playback moves backwards.
+    last_position: Duration,
+}
+
+impl EventStore {
+    pub(crate) fn add_end(&mut self, callback: EndCallback) { self.end.push(callback); }
+
+    pub(crate) fn add_error(&mut self, callback: ErrorCallback) { self.error.push(callback); }
+
+    pub(crate) fn add_position(&mut self, position: Duration, callback: PositionCallback) {
+        self.positions.push(Position {
+            position,
+            fired: false,
+            callback,
+        });
+    }
+
+    pub(crate) fn add_periodic(&mut self,
+                               period: Duration,
+                               callback: PositionCallback) {
+        self.periodic.push(Periodic {
+            next: period,
+            period,
+            callback,
+        });
+    }
+
+    pub(crate) fn raise_error(&mut self, error: TrackError) { self.raised_errors.push(error); }
+
+    /// Runs every callback whose event has come to pass for `audio`.
+    fn fire(&mut self, audio: &mut Audio) {
+        for error in self.raised_errors.drain(..) {
+            for callback in &mut self.error {
+                callback(audio, error);
+            }
+        }
+
+        // After a seek backwards or a loop, callbacks are rearmed from the new
+        // position so that they run again as playback passes them.
+        if audio.position < self.last_position {
+            for periodic in &mut self.periodic {
+                periodic.skip_to(audio.position);
+            }
+        }
+
+        self.last_position = audio.position;
+
+        for position in &mut self.positions {
+            if audio.position < position.position {
+                position.fired = false;
+            } else if !position.fired {
+                position.fired = true;
+
+                (position.callback)(audio);
+            }
+        }
+
+        for periodic in &mut self.periodic {
+            if audio.position < periodic.next {
+                continue;
+            }
+
+            (periodic.callback)(audio);
+
+            // Skip over any ticks missed by seeking forward, rather than
+            // firing them all at once.
+            periodic.skip_to(audio.position);
+        }
+
+        if audio.finished {
+            for mut callback in self.end.drain(..) {
+                callback(audio);
+            }
+        }
+    }
+
+    /// Moves any callbacks registered on `other` into this store.
+    fn absorb(&mut self, mut other: EventStore) {
+        self.end.append(&mut other.end);
+        self.error.append(&mut other.error);
+        self.positions.append(&mut other.positions);
+        self.periodic.append(&mut other.periodic);
+        self.raised_errors.append(&mut other.raised_errors);
+    }
+}
+
+/// Dispatches pending events for the given track.
+pub(crate) fn fire(audio: &mut Audio) {
+    let mut events = mem::replace(&mut audio.events, EventStore::default());
+
+    events.fire(audio);
+
+    // Callbacks may have registered new callbacks on the track while running.
+    let added = mem::replace(&mut audio.events, events);
+    audio.events.absorb(added);
+}
+
+/// Dispatches pending events for every track, then removes those which have
+/// finished.
+pub(crate) fn process(sources: &mut Vec<LockedAudio>) {
+    sources.retain(|aud_lock| {
+        let mut aud = aud_lock.lock();
+
+        fire(&mut aud);
+
+        !aud.finished
+    });
+}
+
+/// Stops every track, firing their end events, then removes them.
+pub(crate) fn stop_all(sources: &mut Vec<LockedAudio>) {
+    for aud_lock in sources.iter() {
+        aud_lock.lock().finished = true;
+    }
+
+    process(sources);
+}
@@ -0,0 +1,150 @@
+use std::time::Duration;
+use super::audio::duration_to_frames;
+use super::LockedAudio;
+
+/// What should happen to a filter, or its track, once it has processed a
+/// frame.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FilterAction {
+    /// Keep the filter in place for the next frame.
+    Continue,
+    /// The filter has no further effect, and should be removed.
+    Remove,
+    /// The track should stop playing, as though it had reached its end.
+    StopTrack,
+}
+
+/// An effect applied to every frame of a single track, before it is mixed with
+/// any other tracks.
+///
+/// Filters are attached to an [`Audio`] through its [`filters`] field or
+/// [`add_filter`] method, and are run in order after the track's volume has
+/// been applied. As they are held by the track, they can be added, removed or
+/// replaced at any time through its [`LockedAudio`] handle.
+///
+/// [`Audio`]: struct.Audio.html
+/// [`LockedAudio`]: type.LockedAudio.html
+/// [`add_filter`]: struct.Audio.html#method.add_filter
+/// [`filters`]: struct.Audio.html#structfield.filters
+pub trait AudioFilter: Send {
+    /// Processes one 20ms frame of interleaved stereo samples in place.
+    ///
+    /// `position` is the track's position at the start of the frame.
+    fn process(&mut self, frame: &mut [f32; 1920], position: Duration) -> FilterAction;
+}
+
+/// Smoothly changes the gain of a track from one level to another over a
+/// period of time.
+///
+/// Once the ramp completes, the final gain continues to be applied, unless it
+/// is `1.0` (in which case the filter removes itself), or the ramp was set to
+/// [`stop`] the track.
+///
+/// [`stop`]: #method.stop
+#[derive(Clone, Debug)]
+pub struct GainRamp {
+    from: f32,
+    to: f32,
+    length: u64,
+    elapsed: u64,
+    start: Option<Duration>,
+    stop: bool,
+}
+
+impl GainRamp {
+    /// Creates a ramp from gain `from` to gain `to`, beginning with the next
+    /// frame played.
+    pub fn new(from: f32, to: f32, duration: Duration) -> Self {
+        Self {
+            from,
+            to,
+            length: duration_to_frames(duration),
+            elapsed: 0,
+            start: None,
+            stop: false,
+        }
+    }
+
+    /// Fades a track in from silence.
+    pub fn fade_in(duration: Duration) -> Self { Self::new(0.0, 1.0, duration) }
+
+    /// Fades a track out to silence, then stops it.
+    pub fn fade_out(duration: Duration) -> Self { Self::new(1.0, 0.0, duration).stop(true) }
+
+    /// Delays the start of the ramp until the track reaches `position`,
+    /// applying the initial gain until then.
+    pub fn starting_at(mut self, position: Duration) -> Self {
+        self.start = Some(position);
+
+        self
+    }
+
+    /// Sets whether the track is stopped once the ramp completes.
+    pub fn stop(mut self, stop: bool) -> Self {
+        self.stop = stop;
+
+        self
+    }
+
+    fn gain_at(&self, frame: u64) -> f32 {
+        if frame >= self.length {
+            return self.to;
+        }
+
+        self.from + (self.to - self.from) * (frame as f32 / self.length as f32)
+    }
+}
+
+impl AudioFilter for GainRamp {
+    fn process(&mut self, frame: &mut [f32; 1920], position: Duration) -> FilterAction {
+        if self.start.map_or(false, |start| position < start) {
+            for sample in frame.iter_mut() {
+                *sample *= self.from;
+            }
+
+            return FilterAction::Continue;
+        }
+
+        let begin = self.gain_at(self.elapsed);
+        let end = self.gain_at(self.elapsed + 1);
+        ramp_gain(frame, begin, end);
+
+        self.elapsed += 1;
+
+        if self.elapsed < self.length {
+            FilterAction::Continue
+        } else if self.stop {
+            FilterAction::StopTrack
+        } else if (self.to - 1.0).abs() < ::std::f32::EPSILON {
+            FilterAction::Remove
+        } else {
+            FilterAction::Continue
+        }
+    }
+}
+
+/// Applies a gain moving from `begin` to `end` over a frame, interpolating
+/// across it so that the gain changes smoothly.
+pub(crate) fn ramp_gain(frame: &mut [f32; 1920], begin: f32, end: f32) {
+    let step = (end - begin) / (frame.len() / 2) as f32;
+
+    for (i, pair) in frame.chunks_mut(2).enumerate() {
+        let gain = begin + step * i as f32;
+
+        for sample in pair {
+            *sample *= gain;
+        }
+    }
+}
+
+/// Fades out the `outgoing` track while fading in the `incoming` track, over
+/// the given `duration`.
+///
+/// The outgoing track is stopped once the fade completes. The incoming track
+/// must be played separately, such as through [`Handler::play_returning`].
+///
+/// [`Handler::play_returning`]: struct.Handler.html#method.play_returning
+pub fn crossfade(outgoing: &LockedAudio, incoming: &LockedAudio, duration: Duration) {
+    outgoing.lock().add_filter(GainRamp::fade_out(duration));
+    incoming.lock().add_filter(GainRamp::fade_in(duration));
+}
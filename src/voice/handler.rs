@@ -6,15 +6,30 @@ use model::{
         GuildId, 
         UserId
     },
-    voice::VoiceState
+    voice::{SpeakingState, VoiceState}
 };
 use parking_lot::Mutex;
 use std::sync::{
-    mpsc::{self, Sender as MpscSender},
+    mpsc::{self, Receiver as MpscReceiver, Sender as MpscSender},
     Arc
 };
 use super::connection_info::ConnectionInfo;
-use super::{Audio, AudioReceiver, AudioSource, Status as VoiceStatus, threading, LockedAudio};
+use super::state::SharedState;
+use super::{
+    Audio,
+    AudioReceiver,
+    AudioSource,
+    ConnectionState,
+    DuckingSettings,
+    EncoderSettings,
+    LockedAudio,
+    MixerPool,
+    PendingJoin,
+    Status as VoiceStatus,
+    threading,
+    TrackQueue,
+    VoiceStats
+};
 
 /// The handler is responsible for "handling" a single voice connection, acting
 /// as a clean API above the inner connection.
@@ -55,6 +70,21 @@ pub struct Handler {
     /// [`guild`]: #structfield.guild
     /// [`switch_to`]: #method.switch_to
     pub channel_id: Option<ChannelId>,
+    /// The settings used to quieten tracks while other users talk, if
+    /// enabled.
+    ///
+    /// **Note**: This _must not_ be manually mutated. Call [`set_ducking`] to
+    /// mutate this value.
+    ///
+    /// [`set_ducking`]: #method.set_ducking
+    pub ducking: Option<DuckingSettings>,
+    /// The settings used to encode audio sent over the connection.
+    ///
+    /// **Note**: This _must not_ be manually mutated. Call
+    /// [`set_encoder_settings`] to mutate this value.
+    ///
+    /// [`set_encoder_settings`]: #method.set_encoder_settings
+    pub encoder_settings: EncoderSettings,
     /// The voice server endpoint.
     pub endpoint: Option<String>,
     /// The Id of the guild to be connected to.
@@ -73,6 +103,12 @@ pub struct Handler {
     ///
     /// [`mute`]: #method.mute
     pub self_mute: bool,
+    /// The mixer pool driving the connection, if it doesn't have a thread of
+    /// its own.
+    pool: Option<MixerPool>,
+    /// The queue of tracks played in order by the voice connection monitor
+    /// thread.
+    queue: TrackQueue,
     /// The internal sender to the voice connection monitor thread.
     sender: MpscSender<VoiceStatus>,
     /// The session Id of the current voice connection, if any.
@@ -81,6 +117,19 @@ pub struct Handler {
     ///
     /// [`update_state`]: #method.update_state
     pub session_id: Option<String>,
+    /// How the current user is shown to be speaking while audio is sent.
+    ///
+    /// **Note**: This _must not_ be manually mutated. Call [`set_speaking`]
+    /// to mutate this value.
+    ///
+    /// [`set_speaking`]: #method.set_speaking
+    pub speaking: SpeakingState,
+    /// The state of the voice connection, shared with the voice connection
+    /// monitor thread.
+    state: SharedState,
+    /// The latest statistics of the connection, shared with the voice
+    /// connection monitor thread.
+    stats: Arc<Mutex<VoiceStats>>,
     /// The token of the current voice connection, if any.
     ///
     /// **Note**: This _should_ be set through an [`update_server`] call.
@@ -109,8 +158,9 @@ impl Handler {
         guild_id: GuildId,
         ws: MpscSender<InterMessage>,
         user_id: UserId,
+        pool: Option<&MixerPool>,
     ) -> Self {
-        Self::new_raw(guild_id, Some(ws), user_id)
+        Self::new_raw(guild_id, Some(ws), user_id, pool)
     }
 
     /// Creates a new, standalone Handler which is not connected to the primary
@@ -124,7 +174,19 @@ impl Handler {
     /// the voice component standalone from the rest of the library.
     #[inline]
     pub fn standalone(guild_id: GuildId, user_id: UserId) -> Self {
-        Self::new_raw(guild_id, None, user_id)
+        Self::new_raw(guild_id, None, user_id, None)
+    }
+
+    /// Creates a new, standalone Handler whose connection is driven by the
+    /// given [`MixerPool`], rather than a thread of its own.
+    ///
+    /// Refer to [`standalone`] for more information.
+    ///
+    /// [`MixerPool`]: struct.MixerPool.html
+    /// [`standalone`]: #method.standalone
+    #[inline]
+    pub fn standalone_pooled(guild_id: GuildId, user_id: UserId, pool: &MixerPool) -> Self {
+        Self::new_raw(guild_id, None, user_id, Some(pool))
     }
 
     /// Connects to the voice channel if the following are present:
@@ -198,6 +260,30 @@ impl Handler {
         self.send_join();
     }
 
+    /// Connect - or switch - to the given voice channel by its Id, returning
+    /// a [`PendingJoin`] which can be used to wait for the connection to be
+    /// established.
+    ///
+    /// [`PendingJoin`]: struct.PendingJoin.html
+    pub fn join_pending(&mut self, channel_id: ChannelId) -> PendingJoin {
+        let pending = self.pending_join(channel_id);
+
+        self.join(channel_id);
+
+        pending
+    }
+
+    /// Creates a [`PendingJoin`] for a join to the given channel which is
+    /// about to be made.
+    ///
+    /// [`PendingJoin`]: struct.PendingJoin.html
+    pub(crate) fn pending_join(&self, channel_id: ChannelId) -> PendingJoin {
+        let connected = self.channel_id == Some(channel_id)
+            && self.state.get() == ConnectionState::Connected;
+
+        PendingJoin::new(self.state.clone(), connected)
+    }
+
     /// Leaves the current voice channel, disconnecting from it.
     ///
     /// This does _not_ forget settings, like whether to be self-deafened or
@@ -278,8 +364,87 @@ impl Handler {
     }
 
     /// Stops playing audio from a source, if one is set.
+    ///
+    /// **Note**: This also ends the [`queue`]'s current track, after which the
+    /// queue moves on to its next track. Use [`TrackQueue::clear`] to stop
+    /// the queue entirely.
+    ///
+    /// [`queue`]: #method.queue
+    /// [`TrackQueue::clear`]: struct.TrackQueue.html#method.clear
     pub fn stop(&mut self) { self.send(VoiceStatus::SetSender(None)) }
 
+    /// Retrieves the queue of tracks attached to this handler.
+    ///
+    /// Tracks added to the queue are played one after another, alongside any
+    /// played via [`play`].
+    ///
+    /// [`play`]: #method.play
+    pub fn queue(&self) -> &TrackQueue { &self.queue }
+
+    /// Adds a source to the back of this handler's [`queue`], returning its
+    /// control handle.
+    ///
+    /// [`queue`]: #method.queue
+    pub fn enqueue(&mut self, source: Box<AudioSource>) -> LockedAudio {
+        self.queue.enqueue(source)
+    }
+
+    /// Retrieves a snapshot of the statistics of the current connection, such
+    /// as the number of packets sent and the latency to the voice server.
+    ///
+    /// The snapshot is updated by the voice thread after every frame, and is
+    /// empty while not connected.
+    pub fn stats(&self) -> VoiceStats { self.stats.lock().clone() }
+
+    /// The current state of the voice connection.
+    pub fn state(&self) -> ConnectionState { self.state.get() }
+
+    /// Subscribes to changes in the state of the voice connection.
+    ///
+    /// Each change is sent over the returned channel, until it is dropped.
+    pub fn subscribe(&self) -> MpscReceiver<ConnectionState> { self.state.subscribe() }
+
+    /// Changes how audio sent over the connection is encoded, such as its
+    /// bitrate or whether it is in stereo.
+    ///
+    /// The new settings are applied by the voice thread from the next frame
+    /// onwards, and are kept for any future connections.
+    pub fn set_encoder_settings(&mut self, settings: EncoderSettings) {
+        self.encoder_settings = settings;
+
+        self.send(VoiceStatus::SetEncoderSettings(settings));
+    }
+
+    /// Sets how tracks marked with [`Audio::duck`] are quietened while other
+    /// users talk, or disables ducking if `None`.
+    ///
+    /// The new settings are kept for any future connections.
+    ///
+    /// [`Audio::duck`]: struct.Audio.html#method.duck
+    pub fn set_ducking(&mut self, settings: Option<DuckingSettings>) {
+        self.ducking = settings;
+
+        self.send(VoiceStatus::SetDucking(settings));
+    }
+
+    /// Sets the flags sent to Discord while audio is being sent. Defaults to
+    /// [`MICROPHONE`].
+    ///
+    /// Bots playing music may prefer [`SOUNDSHARE`], which clients don't
+    /// show as voice activity, and [`PRIORITY`] makes other users quieter
+    /// while the current user is speaking.
+    ///
+    /// The new flags are kept for any future connections.
+    ///
+    /// [`MICROPHONE`]: ../model/voice/struct.SpeakingState.html#associatedconstant.MICROPHONE
+    /// [`PRIORITY`]: ../model/voice/struct.SpeakingState.html#associatedconstant.PRIORITY
+    /// [`SOUNDSHARE`]: ../model/voice/struct.SpeakingState.html#associatedconstant.SOUNDSHARE
+    pub fn set_speaking(&mut self, speaking: SpeakingState) {
+        self.speaking = speaking;
+
+        self.send(VoiceStatus::SetSpeaking(speaking));
+    }
+
     /// Switches the current connected voice channel to the given `channel_id`.
     ///
     /// This has 3 separate behaviors:
@@ -325,6 +490,13 @@ impl Handler {
     /// Refer to the documentation for [`connect`] for when this will
     /// automatically connect to a voice channel.
     ///
+    /// If already connected, then the connection is handed over to the new
+    /// server. Tracks, the receiver and all settings are kept, so playback
+    /// carries on once the new connection is ready. A `None` endpoint means
+    /// that Discord is moving the guild to another server: the current
+    /// connection is closed, and playback is held until the new server is
+    /// given by a later update.
+    ///
     /// [`connect`]: #method.connect
     /// [`standalone`]: #method.standalone
     pub fn update_server(&mut self, endpoint: &Option<String>, token: &str) {
@@ -337,7 +509,9 @@ impl Handler {
                 self.connect();
             }
         } else {
-            self.leave();
+            self.endpoint = None;
+
+            self.send(VoiceStatus::Disconnect);
         }
     }
 
@@ -373,19 +547,30 @@ impl Handler {
         guild_id: GuildId,
         ws: Option<MpscSender<InterMessage>>,
         user_id: UserId,
+        pool: Option<&MixerPool>,
     ) -> Self {
         let (tx, rx) = mpsc::channel();
+        let queue = TrackQueue::new();
+        let stats = Arc::new(Mutex::new(VoiceStats::default()));
+        let state = SharedState::default();
 
-        threading::start(guild_id, rx);
+        threading::start(guild_id, rx, queue.clone(), Arc::clone(&stats), state.clone(), pool);
 
         Handler {
             channel_id: None,
+            ducking: None,
+            encoder_settings: EncoderSettings::default(),
             endpoint: None,
             guild_id,
             self_deaf: false,
             self_mute: false,
+            pool: pool.cloned(),
+            queue,
             sender: tx,
             session_id: None,
+            speaking: SpeakingState::MICROPHONE,
+            state,
+            stats,
             token: None,
             user_id,
             ws,
@@ -401,7 +586,17 @@ impl Handler {
             self.sender = tx;
             self.sender.send(status).unwrap();
 
-            threading::start(self.guild_id, rx);
+            threading::start(self.guild_id,
+                             rx,
+                             self.queue.clone(),
+                             Arc::clone(&self.stats),
+                             self.state.clone(),
+                             self.pool.as_ref());
+
+            // The new thread starts out with the default settings.
+            let _ = self.sender.send(VoiceStatus::SetEncoderSettings(self.encoder_settings));
+            let _ = self.sender.send(VoiceStatus::SetDucking(self.ducking));
+            let _ = self.sender.send(VoiceStatus::SetSpeaking(self.speaking));
 
             self.update();
         }
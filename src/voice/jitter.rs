@@ -0,0 +1,281 @@
+use internal::prelude::*;
+use opus::{
+    packet as opus_packet,
+    Channels,
+    Decoder as OpusDecoder,
+};
+use std::collections::VecDeque;
+use super::audio::{FRAME_SAMPLES, SAMPLE_RATE};
+
+/// Number of frames held back before playout of a stream begins, giving late
+/// packets time to arrive.
+const PLAYOUT_DELAY: usize = 3;
+
+/// Greatest number of frames which may be buffered ahead of playout. Packets
+/// further ahead than this cause the buffer to resynchronise.
+const MAX_BUFFERED: usize = 50;
+
+/// Number of consecutive frames with nothing buffered after which a stream is
+/// considered to have stopped, and playout waits to begin again.
+const MAX_IDLE: usize = 5;
+
+struct Packet {
+    timestamp: u32,
+    payload: Vec<u8>,
+}
+
+/// A frame delivered by a [`JitterBuffer`].
+pub(crate) struct Frame {
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub stereo: bool,
+    /// Number of (interleaved) samples written to the output buffer.
+    pub len: usize,
+    /// The received Opus packet, or `None` if this frame was concealed.
+    pub payload: Option<Vec<u8>>,
+}
+
+/// Reorders the packets of a single received stream, and conceals those which
+/// are lost, so that frames can be handed out at an even 20ms pace.
+pub(crate) struct JitterBuffer {
+    channels: Channels,
+    decoder: OpusDecoder,
+    /// Number of consecutive frames with nothing buffered.
+    idle: usize,
+    next_sequence: u16,
+    next_timestamp: u32,
+    /// Slots for upcoming frames, starting at `next_sequence`.
+    slots: VecDeque<Option<Packet>>,
+    started: bool,
+}
+
+impl JitterBuffer {
+    pub(crate) fn new(channels: Channels) -> Result<Self> {
+        Ok(Self {
+            channels,
+            decoder: OpusDecoder::new(SAMPLE_RATE, channels)?,
+            idle: 0,
+            next_sequence: 0,
+            next_timestamp: 0,
+            slots: VecDeque::new(),
+            started: false,
+        })
+    }
+
+    /// Stores a received Opus packet, to be played out in sequence order.
+    ///
+    /// Packets arriving after their slot has been played out are dropped.
+    pub(crate) fn push(&mut self, sequence: u16, timestamp: u32, payload: Vec<u8>) -> Result<()> {
+        let channels = opus_packet::get_nb_channels(&payload)?;
+
+        if channels != self.channels {
+            self.channels = channels;
+            self.decoder = OpusDecoder::new(SAMPLE_RATE, channels)?;
+        }
+
+        if self.slots.is_empty() && !self.started {
+            self.next_sequence = sequence;
+            self.next_timestamp = timestamp;
+        }
+
+        let offset = sequence.wrapping_sub(self.next_sequence) as i16;
+
+        if offset < 0 {
+            debug!("[Voice] Dropping late packet: seq {}", sequence);
+
+            return Ok(());
+        }
+
+        let mut offset = offset as usize;
+
+        if offset >= MAX_BUFFERED {
+            debug!("[Voice] Packet too far ahead, resynchronising: seq {}", sequence);
+
+            self.reset();
+            self.next_sequence = sequence;
+            self.next_timestamp = timestamp;
+            offset = 0;
+        }
+
+        while self.slots.len() <= offset {
+            self.slots.push_back(None);
+        }
+
+        self.slots[offset] = Some(Packet {
+            timestamp,
+            payload,
+        });
+
+        Ok(())
+    }
+
+    /// Produces the next 20ms frame of the stream into `buffer`, if playout is
+    /// underway.
+    ///
+    /// Missing packets are recovered from the forward error correction data of
+    /// their successor where possible, and concealed otherwise. A packet is
+    /// only known to be missing once a later one has arrived, so nothing is
+    /// produced while the buffer is empty, such as after a speaker stops.
+    pub(crate) fn pop(&mut self, buffer: &mut [i16; 1920]) -> Result<Option<Frame>> {
+        if !self.started {
+            if self.slots.len() < PLAYOUT_DELAY {
+                return Ok(None);
+            }
+
+            self.started = true;
+        }
+
+        let stereo = self.channels == Channels::Stereo;
+        let frame_len = if stereo { FRAME_SAMPLES * 2 } else { FRAME_SAMPLES };
+
+        if self.slots.is_empty() {
+            self.idle += 1;
+
+            if self.idle >= MAX_IDLE {
+                // The stream has stopped: wait for it to begin again.
+                self.reset();
+            }
+
+            return Ok(None);
+        }
+
+        self.idle = 0;
+
+        let (timestamp, len, payload) = match self.slots.pop_front() {
+            Some(Some(packet)) => {
+                match self.decoder.decode(&packet.payload, &mut buffer[..], false) {
+                    Ok(len) => (packet.timestamp, len, Some(packet.payload)),
+                    Err(why) => {
+                        // A corrupt packet is treated as though it were lost.
+                        debug!("[Voice] Concealing undecodable packet: seq {}: {:?}", self.next_sequence, why);
+
+                        (packet.timestamp, self.conceal(&mut buffer[..frame_len], false), None)
+                    },
+                }
+            },
+            _ => {
+                // Lost frames can only be concealed one frame at a time.
+                let timestamp = self.next_timestamp;
+
+                (timestamp, self.conceal(&mut buffer[..frame_len], true), None)
+            },
+        };
+
+        let frame = Frame {
+            sequence: self.next_sequence,
+            timestamp,
+            stereo,
+            len: if stereo { len * 2 } else { len },
+            payload,
+        };
+
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.next_timestamp = timestamp.wrapping_add(len as u32);
+
+        Ok(Some(frame))
+    }
+
+    /// Fills `out` with a single concealed frame, using the forward error
+    /// correction data of the next packet if `fec` is set and it is present.
+    ///
+    /// Falls back to silence if the decoder cannot conceal the frame, so that
+    /// playout always advances by one frame.
+    fn conceal(&mut self, out: &mut [i16], fec: bool) -> usize {
+        let recovered = match self.slots.front() {
+            Some(Some(next)) if fec => self.decoder.decode(&next.payload, out, true).ok(),
+            _ => None,
+        };
+
+        match recovered.or_else(|| self.decoder.decode(&[], out, false).ok()) {
+            Some(len) => len,
+            None => {
+                for sample in out.iter_mut() {
+                    *sample = 0;
+                }
+
+                FRAME_SAMPLES
+            },
+        }
+    }
+
+    fn reset(&mut self) {
+        self.idle = 0;
+        self.slots.clear();
+        self.started = false;
+        let _ = self.decoder.reset_state();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opus::{Application, Channels, Encoder as OpusEncoder};
+    use super::*;
+
+    #[test]
+    fn corrupt_packet_is_concealed() {
+        let mut encoder = OpusEncoder::new(SAMPLE_RATE, Channels::Mono, Application::Audio).unwrap();
+        let good = encoder.encode_vec(&[0i16; FRAME_SAMPLES], 4000).unwrap();
+
+        // A mono code 3 packet claiming 63 frames, which is longer than any
+        // valid packet.
+        let corrupt = vec![0xfb, 0xff, 0xff];
+
+        let mut jitter = JitterBuffer::new(Channels::Mono).unwrap();
+        jitter.push(10, 0, corrupt).unwrap();
+
+        for i in 1..4 {
+            jitter.push(10 + i, 960 * u32::from(i), good.clone()).unwrap();
+        }
+
+        let mut buffer = [0i16; 1920];
+
+        let frame = jitter.pop(&mut buffer).unwrap().unwrap();
+        assert_eq!(frame.sequence, 10);
+        assert_eq!(frame.len, FRAME_SAMPLES);
+        assert!(frame.payload.is_none());
+
+        for i in 1..4 {
+            let frame = jitter.pop(&mut buffer).unwrap().unwrap();
+            assert_eq!(frame.sequence, 10 + i);
+            assert_eq!(frame.timestamp, 960 * u32::from(i));
+            assert!(frame.payload.is_some());
+        }
+    }
+
+    #[test]
+    fn stopped_stream_is_not_concealed() {
+        let mut encoder = OpusEncoder::new(SAMPLE_RATE, Channels::Mono, Application::Audio).unwrap();
+        let good = encoder.encode_vec(&[0i16; FRAME_SAMPLES], 4000).unwrap();
+
+        let mut jitter = JitterBuffer::new(Channels::Mono).unwrap();
+        let mut buffer = [0i16; 1920];
+
+        for i in 0..3 {
+            jitter.push(i, 960 * u32::from(i), good.clone()).unwrap();
+        }
+
+        for _ in 0..3 {
+            assert!(jitter.pop(&mut buffer).unwrap().unwrap().payload.is_some());
+        }
+
+        for _ in 0..MAX_IDLE * 2 {
+            assert!(jitter.pop(&mut buffer).unwrap().is_none());
+        }
+
+        // Once a later packet has arrived, the one missing before it is
+        // concealed.
+        let mut jitter = JitterBuffer::new(Channels::Mono).unwrap();
+
+        for &i in &[0, 1, 2, 4] {
+            jitter.push(i, 960 * u32::from(i), good.clone()).unwrap();
+        }
+
+        let sequences = (0..5)
+            .map(|_| jitter.pop(&mut buffer).unwrap().unwrap())
+            .map(|frame| (frame.sequence, frame.payload.is_some()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(sequences, vec![(0, true), (1, true), (2, true), (3, false), (4, true)]);
+        assert!(jitter.pop(&mut buffer).unwrap().is_none());
+    }
+}
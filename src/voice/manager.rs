@@ -4,7 +4,7 @@ use std::{
     collections::HashMap,
     sync::mpsc::Sender as MpscSender
 };
-use super::Handler;
+use super::{Handler, MixerPool, PendingJoin};
 
 /// A manager is a struct responsible for managing [`Handler`]s which belong to
 /// a single [`Shard`]. This is a fairly complex key-value store,
@@ -25,6 +25,7 @@ use super::Handler;
 #[derive(Clone, Debug)]
 pub struct Manager {
     handlers: HashMap<GuildId, Handler>,
+    pool: Option<MixerPool>,
     user_id: UserId,
     ws: MpscSender<InterMessage>,
 }
@@ -33,6 +34,7 @@ impl Manager {
     pub(crate) fn new(ws: MpscSender<InterMessage>, user_id: UserId) -> Manager {
         Manager {
             handlers: HashMap::new(),
+            pool: None,
             user_id,
             ws,
         }
@@ -91,7 +93,7 @@ impl Manager {
             }
         }
 
-        let mut handler = Handler::new(guild_id, self.ws.clone(), self.user_id);
+        let mut handler = Handler::new(guild_id, self.ws.clone(), self.user_id, self.pool.as_ref());
         handler.join(channel_id);
 
         self.handlers.insert(guild_id, handler);
@@ -100,6 +102,36 @@ impl Manager {
         self.handlers.get_mut(&guild_id).unwrap()
     }
 
+    /// Joins a target as with [`join`], returning a [`PendingJoin`] which can
+    /// be used to wait for the connection to be established.
+    ///
+    /// The connection can only be completed once the lock on the manager has
+    /// been released.
+    ///
+    /// [`PendingJoin`]: struct.PendingJoin.html
+    /// [`join`]: #method.join
+    pub fn join_pending<C, G>(&mut self, guild_id: G, channel_id: C) -> PendingJoin
+        where C: Into<ChannelId>, G: Into<GuildId> {
+        let channel_id = channel_id.into();
+        let guild_id = guild_id.into();
+
+        let pending = self.handlers
+            .get(&guild_id)
+            .map(|handler| handler.pending_join(channel_id));
+
+        let handler = self.join(guild_id, channel_id);
+
+        pending.unwrap_or_else(|| handler.pending_join(channel_id))
+    }
+
+    /// Sets the [`MixerPool`] used to drive the connections of handlers
+    /// created from now on, or `None` to give each its own thread.
+    ///
+    /// Existing handlers are unaffected.
+    ///
+    /// [`MixerPool`]: struct.MixerPool.html
+    pub fn set_mixer_pool(&mut self, pool: Option<MixerPool>) { self.pool = pool; }
+
     /// Retrieves the [handler][`Handler`] for the given target and leaves the
     /// associated voice channel, if connected.
     ///
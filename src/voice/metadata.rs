@@ -0,0 +1,112 @@
+use internal::prelude::*;
+use std::time::Duration;
+
+/// Information about a track, as found by the function which created its
+/// source.
+///
+/// The metadata of a playing track is available through its [`metadata`]
+/// field. Fields which could not be determined are left as `None`.
+///
+/// [`metadata`]: struct.Audio.html#structfield.metadata
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AudioMetadata {
+    /// The title of the track.
+    pub title: Option<String>,
+    /// The artist or uploader of the track.
+    pub artist: Option<String>,
+    /// The length of the track.
+    pub duration: Option<Duration>,
+    /// A URL to a thumbnail image for the track.
+    pub thumbnail: Option<String>,
+    /// The sample rate of the original audio, in Hz.
+    pub sample_rate: Option<u32>,
+    /// The number of channels in the original audio.
+    pub channels: Option<u8>,
+    /// A URL to the page the track was taken from.
+    pub source_url: Option<String>,
+}
+
+impl AudioMetadata {
+    /// Reads metadata from the JSON output of `youtube-dl`.
+    pub(crate) fn from_ytdl(obj: &JsonMap) -> Self {
+        let string = |key: &str| obj.get(key).and_then(Value::as_str).map(str::to_owned);
+
+        Self {
+            title: string("title"),
+            artist: string("artist").or_else(|| string("uploader")),
+            duration: obj.get("duration").and_then(Value::as_f64).map(seconds_to_duration),
+            thumbnail: string("thumbnail"),
+            sample_rate: obj.get("asr").and_then(Value::as_u64).map(|rate| rate as u32),
+            channels: obj.get("audio_channels").and_then(Value::as_u64).map(|channels| channels as u8),
+            source_url: string("webpage_url"),
+        }
+    }
+
+    /// Reads metadata from the JSON output of `ffprobe`, run with
+    /// `-show_streams` and `-show_format`.
+    pub(crate) fn from_ffprobe(value: &Value) -> Self {
+        let format = value.get("format");
+
+        // Only the first audio stream is played.
+        let stream = value
+            .get("streams")
+            .and_then(Value::as_array)
+            .and_then(|streams| {
+                streams
+                    .iter()
+                    .find(|stream| stream.get("codec_type").and_then(Value::as_str) == Some("audio"))
+            });
+
+        // Tags may be set on the container or the stream, and their case
+        // depends on the format.
+        let tag = |key: &str| {
+            format
+                .into_iter()
+                .chain(stream)
+                .filter_map(|item| item.get("tags").and_then(Value::as_object))
+                .flat_map(|tags| tags.iter())
+                .find(|&(name, _)| name.eq_ignore_ascii_case(key))
+                .and_then(|(_, value)| value.as_str())
+                .map(str::to_owned)
+        };
+
+        Self {
+            title: tag("title"),
+            artist: tag("artist"),
+            duration: format
+                .and_then(|format| format.get("duration"))
+                .and_then(Value::as_str)
+                .and_then(|duration| duration.parse::<f64>().ok())
+                .map(seconds_to_duration),
+            thumbnail: None,
+            sample_rate: stream
+                .and_then(|stream| stream.get("sample_rate"))
+                .and_then(Value::as_str)
+                .and_then(|rate| rate.parse().ok()),
+            channels: stream
+                .and_then(|stream| stream.get("channels"))
+                .and_then(Value::as_u64)
+                .map(|channels| channels as u8),
+            source_url: None,
+        }
+    }
+
+    /// Fills in any fields which are missing with those of `other`.
+    pub(crate) fn merge(self, other: AudioMetadata) -> Self {
+        Self {
+            title: self.title.or(other.title),
+            artist: self.artist.or(other.artist),
+            duration: self.duration.or(other.duration),
+            thumbnail: self.thumbnail.or(other.thumbnail),
+            sample_rate: self.sample_rate.or(other.sample_rate),
+            channels: self.channels.or(other.channels),
+            source_url: self.source_url.or(other.source_url),
+        }
+    }
+}
+
+fn seconds_to_duration(seconds: f64) -> Duration {
+    let seconds = seconds.max(0.0);
+
+    Duration::new(seconds.trunc() as u64, (seconds.fract() * 1e9) as u32)
+}
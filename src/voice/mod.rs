@@ -1,15 +1,32 @@
 //! A module for connecting to voice channels.
 
 mod audio;
+mod cached;
 mod connection;
 mod connection_info;
+mod crypto;
 mod dca;
+mod ducking;
+mod encoder;
 mod error;
+mod events;
+mod filters;
 mod manager;
+mod metadata;
 mod handler;
+mod jitter;
+mod ogg;
 mod payload;
+mod pool;
+mod process;
+mod queue;
+mod recorder;
+mod state;
+mod stats;
 mod streamer;
 mod threading;
+mod vad;
+mod wav;
 
 pub use self::{
     audio::{
@@ -17,29 +34,58 @@ pub use self::{
         AudioReceiver, 
         AudioSource, 
         AudioType, 
-        LockedAudio
+        LockedAudio,
+        LoopState
     },
-    dca::DcaMetadata,
-    error::{DcaError, VoiceError},
+    cached::CachedAudio,
+    dca::{
+        DcaFormat,
+        DcaMetadata,
+        DcaOpus,
+        DcaOrigin,
+        DcaTool,
+        DcaTrackInfo,
+        DcaWriter
+    },
+    ducking::{DuckingSettings, DuckingTrigger},
+    encoder::EncoderSettings,
+    error::{DcaError, OggError, VoiceError, WavError},
+    events::TrackError,
+    filters::{crossfade, AudioFilter, FilterAction, GainRamp},
     handler::Handler,
     manager::Manager,
+    metadata::AudioMetadata,
+    pool::MixerPool,
+    process::ProcessInput,
+    queue::{LoopMode, QueueSnapshot, TrackQueue},
+    recorder::{Recorder, RecordingFormat},
+    state::{ConnectionState, PendingJoin},
+    stats::{ReceivedStats, VoiceStats},
     streamer::{
         dca, 
         ffmpeg, 
+        ogg,
         opus, 
         pcm,
+        pcm_seekable,
+        wav,
         ytdl
-    }
+    },
+    vad::{ActivityEvent, SpeakerStats, SpeakingAnalytics, VoiceActivity}
 };
 
 use self::connection_info::ConnectionInfo;
+use model::voice::SpeakingState;
 
-const CRYPTO_MODE: &'static str = "xsalsa20_poly1305";
+pub use opus::{Application as CodingMode, Bitrate};
 
 pub(crate) enum Status {
     Connect(ConnectionInfo),
-    #[allow(dead_code)] Disconnect,
+    Disconnect,
     SetReceiver(Option<Box<AudioReceiver>>),
     SetSender(Option<LockedAudio>),
     AddSender(LockedAudio),
+    SetEncoderSettings(EncoderSettings),
+    SetDucking(Option<DuckingSettings>),
+    SetSpeaking(SpeakingState),
 }
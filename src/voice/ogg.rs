@@ -0,0 +1,328 @@
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use internal::prelude::*;
+use sodiumoxide::randombytes;
+use std::{
+    collections::VecDeque,
+    io::{
+        Error as IoError,
+        ErrorKind as IoErrorKind,
+        Read,
+        Result as IoResult,
+        Write
+    },
+    result::Result as StdResult
+};
+use super::audio::SAMPLE_RATE;
+use super::OggError;
+
+const HEADER_TYPE_BOS: u8 = 0x02;
+const HEADER_TYPE_EOS: u8 = 0x04;
+
+/// Number of packets gathered into each audio page (one second of audio).
+const PACKETS_PER_PAGE: usize = 50;
+
+/// Greatest number of lacing values which a single page may hold.
+const MAX_SEGMENTS: usize = 255;
+
+/// Writes a stream of Opus packets into an Ogg Opus container.
+pub(crate) struct OggOpusWriter<W: Write> {
+    granule: u64,
+    inner: W,
+    page_sequence: u32,
+    pending: Vec<Vec<u8>>,
+    pending_segments: usize,
+    serial: u32,
+}
+
+impl<W: Write> OggOpusWriter<W> {
+    /// Begins a new Ogg Opus stream, writing its headers.
+    pub(crate) fn new(inner: W, channels: u8, pre_skip: u16) -> Result<Self> {
+        let mut serial = [0u8; 4];
+        randombytes::randombytes_into(&mut serial);
+
+        let mut writer = Self {
+            granule: 0,
+            inner,
+            page_sequence: 0,
+            pending: Vec::new(),
+            pending_segments: 0,
+            serial: LittleEndian::read_u32(&serial),
+        };
+
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.write_u8(1)?;
+        head.write_u8(channels)?;
+        head.write_u16::<LittleEndian>(pre_skip)?;
+        head.write_u32::<LittleEndian>(SAMPLE_RATE)?;
+        // Output gain and channel mapping family.
+        head.write_i16::<LittleEndian>(0)?;
+        head.write_u8(0)?;
+
+        let vendor = b"serenity";
+        let mut tags = Vec::with_capacity(16 + vendor.len());
+        tags.extend_from_slice(b"OpusTags");
+        tags.write_u32::<LittleEndian>(vendor.len() as u32)?;
+        tags.extend_from_slice(vendor);
+        tags.write_u32::<LittleEndian>(0)?;
+
+        // Both headers must sit alone on their own pages.
+        writer.write_page(&[head], 0, HEADER_TYPE_BOS)?;
+        writer.write_page(&[tags], 0, 0)?;
+
+        Ok(writer)
+    }
+
+    /// Adds an Opus packet containing `samples` samples (per channel) to the
+    /// stream.
+    pub(crate) fn write_packet(&mut self, packet: &[u8], samples: u64) -> Result<()> {
+        // Packets are never split across pages, so that each page's granule
+        // position marks the end of its final packet.
+        let segments = packet.len() / 255 + 1;
+
+        if self.pending_segments + segments > MAX_SEGMENTS {
+            self.flush_packets(0)?;
+        }
+
+        self.granule += samples;
+        self.pending.push(packet.to_vec());
+        self.pending_segments += segments;
+
+        if self.pending.len() >= PACKETS_PER_PAGE {
+            self.flush_packets(0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes out any buffered packets and marks the end of the stream.
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        self.flush_packets(HEADER_TYPE_EOS)?;
+        self.inner.flush()?;
+
+        Ok(())
+    }
+
+    fn flush_packets(&mut self, header_type: u8) -> Result<()> {
+        let packets = ::std::mem::replace(&mut self.pending, Vec::new());
+        let granule = self.granule;
+        self.pending_segments = 0;
+
+        self.write_page(&packets, granule, header_type)
+    }
+
+    fn write_page(&mut self, packets: &[Vec<u8>], granule: u64, header_type: u8) -> Result<()> {
+        // Every packet is laced into 255-byte segments, terminated by a segment
+        // of less than 255 bytes.
+        let mut lacing = Vec::new();
+
+        for packet in packets {
+            lacing.extend(::std::iter::repeat(255).take(packet.len() / 255));
+            lacing.push((packet.len() % 255) as u8);
+        }
+
+        let body_len = packets.iter().map(|p| p.len()).sum::<usize>();
+
+        let mut page = Vec::with_capacity(27 + lacing.len() + body_len);
+        page.extend_from_slice(b"OggS");
+        page.write_u8(0)?;
+        page.write_u8(header_type)?;
+        page.write_u64::<LittleEndian>(granule)?;
+        page.write_u32::<LittleEndian>(self.serial)?;
+        page.write_u32::<LittleEndian>(self.page_sequence)?;
+        page.write_u32::<LittleEndian>(0)?;
+        page.write_u8(lacing.len() as u8)?;
+        page.extend_from_slice(&lacing);
+
+        for packet in packets {
+            page.extend_from_slice(packet);
+        }
+
+        let crc = crc32(&page);
+        LittleEndian::write_u32(&mut page[22..26], crc);
+
+        self.inner.write_all(&page)?;
+        self.page_sequence = self.page_sequence.wrapping_add(1);
+
+        Ok(())
+    }
+}
+
+/// Reads the packets of the first Opus stream in an Ogg container.
+pub(crate) struct OggOpusReader<R: Read> {
+    channels: u8,
+    inner: R,
+    packets: VecDeque<Vec<u8>>,
+    /// The start of a packet which continues onto the next page.
+    partial: Vec<u8>,
+    pre_skip: u16,
+    serial: u32,
+}
+
+impl<R: Read> OggOpusReader<R> {
+    /// Reads the stream's identification and comment headers, leaving the
+    /// reader at the first page of audio.
+    pub(crate) fn new(inner: R) -> StdResult<Self, OggError> {
+        let mut reader = Self {
+            channels: 0,
+            inner,
+            packets: VecDeque::new(),
+            partial: Vec::new(),
+            pre_skip: 0,
+            serial: 0,
+        };
+
+        let head = loop {
+            let (header_type, serial, packets) = match reader.read_page() {
+                Ok(Some(page)) => page,
+                Ok(None) => return Err(OggError::NotOpus),
+                Err(why) => return Err(OggError::from_io(why)),
+            };
+
+            if header_type & HEADER_TYPE_BOS != 0 && packets.len() == 1
+                && packets[0].starts_with(b"OpusHead") {
+                reader.serial = serial;
+
+                break packets[0].clone();
+            }
+        };
+
+        if head.len() < 19 {
+            return Err(OggError::NotOpus);
+        }
+
+        reader.channels = head[9];
+        reader.pre_skip = LittleEndian::read_u16(&head[10..12]);
+
+        if reader.channels == 0 || reader.channels > 2 {
+            return Err(OggError::UnsupportedChannels(reader.channels));
+        }
+
+        match reader.next_packet() {
+            Ok(Some(ref tags)) if tags.starts_with(b"OpusTags") => {},
+            Ok(_) => return Err(OggError::NotOpus),
+            Err(why) => return Err(OggError::from_io(why)),
+        }
+
+        Ok(reader)
+    }
+
+    pub(crate) fn is_stereo(&self) -> bool { self.channels == 2 }
+
+    /// Number of samples per channel, at 48kHz, which the encoder prepended
+    /// to the stream and which should be discarded after decoding.
+    pub(crate) fn pre_skip(&self) -> usize { usize::from(self.pre_skip) }
+
+    pub(crate) fn get_mut(&mut self) -> &mut R { &mut self.inner }
+
+    /// Discards any packets read ahead of the reader's position, such as after
+    /// the underlying reader has been seeked.
+    pub(crate) fn clear(&mut self) {
+        self.packets.clear();
+        self.partial.clear();
+    }
+
+    /// Returns the next packet of the stream, or `None` once it has ended.
+    pub(crate) fn next_packet(&mut self) -> IoResult<Option<Vec<u8>>> {
+        loop {
+            if let Some(packet) = self.packets.pop_front() {
+                return Ok(Some(packet));
+            }
+
+            let (_, serial, packets) = match self.read_page()? {
+                Some(page) => page,
+                None => return Ok(None),
+            };
+
+            // Pages of any other multiplexed streams are ignored.
+            if serial == self.serial {
+                self.packets.extend(packets);
+            }
+        }
+    }
+
+    /// Reads a single page, returning its header type, serial number and the
+    /// packets which it completes.
+    fn read_page(&mut self) -> IoResult<Option<(u8, u32, Vec<Vec<u8>>)>> {
+        let mut header = [0u8; 27];
+
+        match self.inner.read_exact(&mut header) {
+            Ok(()) => {},
+            Err(ref e) if e.kind() == IoErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        if &header[..4] != b"OggS" || header[4] != 0 {
+            return Err(invalid_data("invalid Ogg page header"));
+        }
+
+        let header_type = header[5];
+        let serial = LittleEndian::read_u32(&header[14..18]);
+        let checksum = LittleEndian::read_u32(&header[22..26]);
+
+        let mut lacing = vec![0u8; header[26] as usize];
+        self.inner.read_exact(&mut lacing)?;
+
+        let body_len = lacing.iter().map(|&len| len as usize).sum::<usize>();
+        let mut body = vec![0u8; body_len];
+        self.inner.read_exact(&mut body)?;
+
+        let mut page = Vec::with_capacity(27 + lacing.len() + body_len);
+        page.extend_from_slice(&header);
+        page.extend_from_slice(&lacing);
+        page.extend_from_slice(&body);
+        LittleEndian::write_u32(&mut page[22..26], 0);
+
+        if crc32(&page) != checksum {
+            return Err(invalid_data("Ogg page checksum mismatch"));
+        }
+
+        let mut packets = Vec::new();
+
+        // Pages from other streams don't carry on this stream's packets.
+        let mut partial = if serial == self.serial {
+            ::std::mem::replace(&mut self.partial, Vec::new())
+        } else {
+            Vec::new()
+        };
+
+        let mut offset = 0;
+
+        for &len in &lacing {
+            partial.extend_from_slice(&body[offset..offset + len as usize]);
+            offset += len as usize;
+
+            if len < 255 {
+                packets.push(::std::mem::replace(&mut partial, Vec::new()));
+            }
+        }
+
+        if serial == self.serial {
+            self.partial = partial;
+        }
+
+        Ok(Some((header_type, serial, packets)))
+    }
+}
+
+fn invalid_data(message: &str) -> IoError { IoError::new(IoErrorKind::InvalidData, message) }
+
+/// The CRC used by Ogg pages: polynomial `0x04c11db7`, unreflected, with no
+/// initial or final XOR.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+
+    for &byte in data {
+        crc ^= u32::from(byte) << 24;
+
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
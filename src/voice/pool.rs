@@ -0,0 +1,188 @@
+use internal::Timer;
+use parking_lot::Mutex;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    mpsc::{self, Receiver as MpscReceiver, Sender as MpscSender, TryRecvError},
+    Arc
+};
+use std::thread::Builder as ThreadBuilder;
+use threadpool::ThreadPool;
+use super::threading::Runner;
+
+/// A pool of mixer threads, each of which drives the voice connections of many
+/// guilds on a shared 20ms tick.
+///
+/// By default, every [`Handler`] runs its connection on a thread of its own,
+/// alongside threads for receiving over UDP and the voice websocket. Handlers
+/// created after a pool is given to a [`Manager`] through
+/// [`Manager::set_mixer_pool`] are instead spread across the pool's threads,
+/// and poll their UDP sockets and websockets without blocking. Connecting and
+/// reconnecting is done on a fixed number of connector threads, also owned by
+/// the pool, so that other guilds aren't held up.
+///
+/// Everything a handler would run on its own thread is instead run on its
+/// mixer thread: reading its audio sources, and calling its [`AudioReceiver`],
+/// filters and track callbacks. These should not block, as a stall holds up
+/// every guild on the thread. In particular, sources reading from a pipe, such
+/// as [`ffmpeg`] and [`ytdl`], wait on the process whenever it falls behind;
+/// short clips can be read ahead of time, off the mixer thread, into a
+/// [`CachedAudio`]. A handler whose code
+/// panics is dropped from its thread, and is restarted on the next message
+/// sent to it.
+///
+/// Cloning a pool is cheap, and clones share the same threads, so one pool
+/// can be given to the managers of every shard. Its threads stop once every
+/// clone, and every handler using it, has been dropped.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use serenity::voice::MixerPool;
+///
+/// let pool = MixerPool::new(4);
+///
+/// // For the voice manager of each shard:
+/// manager.lock().set_mixer_pool(Some(pool.clone()));
+/// ```
+///
+/// [`AudioReceiver`]: trait.AudioReceiver.html
+/// [`CachedAudio`]: struct.CachedAudio.html
+/// [`Handler`]: struct.Handler.html
+/// [`Manager`]: struct.Manager.html
+/// [`Manager::set_mixer_pool`]: struct.Manager.html#method.set_mixer_pool
+/// [`ffmpeg`]: fn.ffmpeg.html
+/// [`ytdl`]: fn.ytdl.html
+#[derive(Clone, Debug)]
+pub struct MixerPool {
+    inner: Arc<PoolInner>,
+}
+
+#[derive(Debug)]
+struct PoolInner {
+    /// Runs connection handshakes, which block for too long to be run on a
+    /// mixer thread.
+    connector: Mutex<ThreadPool>,
+    next: AtomicUsize,
+    workers: Vec<Mutex<MpscSender<Runner>>>,
+}
+
+impl MixerPool {
+    /// Starts a pool with the given number of mixer threads, and as many
+    /// connector threads.
+    ///
+    /// At least one of each is always started.
+    pub fn new(threads: usize) -> Self {
+        let threads = threads.max(1);
+
+        let workers = (0..threads)
+            .map(|i| {
+                let (tx, rx) = mpsc::channel();
+
+                ThreadBuilder::new()
+                    .name(format!("Serenity Voice Mixer {}", i))
+                    .spawn(move || mixer(&rx))
+                    .expect("[Voice] Error starting mixer thread");
+
+                Mutex::new(tx)
+            })
+            .collect();
+
+        Self {
+            inner: Arc::new(PoolInner {
+                connector: Mutex::new(ThreadPool::with_name("Serenity Voice Connector".to_string(), threads)),
+                next: AtomicUsize::new(0),
+                workers,
+            }),
+        }
+    }
+
+    /// The number of mixer threads in the pool.
+    pub fn threads(&self) -> usize { self.inner.workers.len() }
+
+    /// The threads on which the pool's handlers establish connections.
+    pub(crate) fn connector(&self) -> ThreadPool { self.inner.connector.lock().clone() }
+
+    /// Hands a runner over to one of the pool's threads, returning it if the
+    /// thread has stopped.
+    pub(crate) fn add(&self, runner: Runner) -> Result<(), Runner> {
+        let index = self.inner.next.fetch_add(1, Ordering::Relaxed) % self.inner.workers.len();
+
+        self.inner.workers[index]
+            .lock()
+            .send(runner)
+            .map_err(|mpsc::SendError(runner)| runner)
+    }
+}
+
+fn mixer(rx: &MpscReceiver<Runner>) {
+    let mut runners: Vec<Runner> = Vec::new();
+    let mut mixed = Vec::new();
+    let mut timer = Timer::new(20);
+
+    loop {
+        // Sleep until there's something to do, rather than ticking over with
+        // no connections.
+        if runners.is_empty() {
+            match rx.recv() {
+                Ok(runner) => runners.push(runner),
+                Err(_) => return,
+            }
+
+            timer.reset();
+        }
+
+        loop {
+            match rx.try_recv() {
+                Ok(runner) => runners.push(runner),
+                Err(TryRecvError::Empty) => break,
+                // Keep driving the existing runners until their handlers are
+                // dropped.
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+
+        // Runners whose handlers have been dropped are shut down, along with
+        // their connections.
+        let mut i = 0;
+
+        while i < runners.len() {
+            match guard(&mut runners[i], Runner::receive) {
+                Some(true) => i += 1,
+                Some(false) => drop(runners.swap_remove(i)),
+                None => runners.swap_remove(i).abandon(),
+            }
+        }
+
+        // Every connection's frame is mixed before any are sent, so that all
+        // are sent as close to the tick as possible.
+        let mut i = 0;
+
+        while i < runners.len() {
+            match guard(&mut runners[i], Runner::mix) {
+                Some(frame) => {
+                    mixed.push(frame);
+                    i += 1;
+                },
+                None => runners.swap_remove(i).abandon(),
+            }
+        }
+
+        let tick = timer.await();
+        let mut i = 0;
+
+        for frame in mixed.drain(..) {
+            match guard(&mut runners[i], |runner| runner.send(frame, tick)) {
+                Some(()) => i += 1,
+                None => runners.remove(i).abandon(),
+            }
+        }
+    }
+}
+
+/// Runs part of a runner's frame, catching any panic so that only that runner
+/// is lost, rather than every guild on the thread.
+fn guard<T, F>(runner: &mut Runner, f: F) -> Option<T>
+    where F: FnOnce(&mut Runner) -> T {
+    panic::catch_unwind(AssertUnwindSafe(|| f(runner))).ok()
+}
@@ -0,0 +1,528 @@
+use internal::prelude::*;
+use parking_lot::Mutex;
+use std::{
+    collections::VecDeque,
+    ffi::{OsStr, OsString},
+    io::{
+        Error as IoError,
+        ErrorKind as IoErrorKind,
+        Read,
+        Result as IoResult
+    },
+    process::{
+        Child,
+        Command,
+        ExitStatus,
+        Stdio
+    },
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration
+};
+use super::metadata::AudioMetadata;
+use super::streamer::{probe, InputSource};
+use super::{AudioSource, AudioType, VoiceError};
+
+/// The number of lines of stderr kept by default.
+const STDERR_LINES: usize = 20;
+
+/// A builder for audio sources read from an `ffmpeg` child process, allowing
+/// its arguments and binary to be customised.
+///
+/// The source is read as 16-bit PCM from `ffmpeg`'s stdout. If `ffmpeg`, or a
+/// [pre-processor] feeding it, exits unsuccessfully, then the track raises a
+/// [`TrackError::Read`] and its [`error`] is set to a
+/// [`VoiceError::ProcessFailed`] holding the last lines either process wrote
+/// to stderr.
+///
+/// [`ffmpeg`] is a shorthand for this builder with no options set.
+///
+/// # Examples
+///
+/// Playing part of a file with an audio filter, through a custom build of
+/// `ffmpeg`:
+///
+/// ```rust,ignore
+/// use serenity::voice::ProcessInput;
+///
+/// let source = ProcessInput::new("song.flac")
+///     .binary("/opt/ffmpeg/bin/ffmpeg")
+///     .input_args(&["-ss", "30"])
+///     .output_args(&["-af", "atempo=1.25"])
+///     .build()?;
+/// ```
+///
+/// Streaming through `youtube-dl`, rather than handing its URL to `ffmpeg`:
+///
+/// ```rust,ignore
+/// use serenity::voice::ProcessInput;
+///
+/// let source = ProcessInput::new("https://www.youtube.com/watch?v=dQw4w9WgXcQ")
+///     .pre_process("youtube-dl", &["-f", "bestaudio", "-q", "-o", "-"])
+///     .stereo(true)
+///     .build()?;
+/// ```
+///
+/// [`TrackError::Read`]: enum.TrackError.html#variant.Read
+/// [`VoiceError::ProcessFailed`]: enum.VoiceError.html#variant.ProcessFailed
+/// [`error`]: struct.Audio.html#structfield.error
+/// [`ffmpeg`]: fn.ffmpeg.html
+/// [pre-processor]: #method.pre_process
+#[derive(Clone, Debug)]
+pub struct ProcessInput {
+    binary: OsString,
+    input: OsString,
+    input_args: Vec<OsString>,
+    metadata: AudioMetadata,
+    output_args: Vec<OsString>,
+    pre_process: Option<(OsString, Vec<OsString>)>,
+    stderr_lines: usize,
+    stereo: Option<bool>,
+}
+
+impl ProcessInput {
+    /// Creates a builder reading from the given path or URL.
+    pub fn new<P: AsRef<OsStr>>(input: P) -> Self {
+        let input = input.as_ref().to_os_string();
+        let mut metadata = AudioMetadata::default();
+
+        if let Some(input) = input.to_str() {
+            if input.contains("://") {
+                metadata.source_url = Some(input.to_owned());
+            }
+        }
+
+        Self {
+            binary: OsString::from("ffmpeg"),
+            input,
+            input_args: Vec::new(),
+            metadata,
+            output_args: Vec::new(),
+            pre_process: None,
+            stderr_lines: STDERR_LINES,
+            stereo: None,
+        }
+    }
+
+    /// Sets the path of the `ffmpeg` binary to run.
+    ///
+    /// Defaults to `ffmpeg`, found through the `PATH`.
+    pub fn binary<P: AsRef<OsStr>>(mut self, path: P) -> Self {
+        self.binary = path.as_ref().to_os_string();
+
+        self
+    }
+
+    /// Adds arguments to be placed before the input, such as HTTP headers or
+    /// the input's format.
+    pub fn input_args<I, S>(mut self, args: I) -> Self
+        where I: IntoIterator<Item = S>, S: AsRef<OsStr> {
+        self.input_args.extend(args.into_iter().map(|arg| arg.as_ref().to_os_string()));
+
+        self
+    }
+
+    /// Adds arguments to be placed after the input, such as audio filters.
+    ///
+    /// These come before the arguments which set the output format, so they
+    /// can't be used to change it.
+    pub fn output_args<I, S>(mut self, args: I) -> Self
+        where I: IntoIterator<Item = S>, S: AsRef<OsStr> {
+        self.output_args.extend(args.into_iter().map(|arg| arg.as_ref().to_os_string()));
+
+        self
+    }
+
+    /// Runs another program on the input, piping its stdout into `ffmpeg`.
+    ///
+    /// The input is passed to the program after the given arguments, and
+    /// `ffmpeg` reads from its stdin instead. The input can't be inspected
+    /// with `ffprobe` in this case, so [`stereo`] should usually be set.
+    ///
+    /// [`stereo`]: #method.stereo
+    pub fn pre_process<P, I, S>(mut self, program: P, args: I) -> Self
+        where P: AsRef<OsStr>, I: IntoIterator<Item = S>, S: AsRef<OsStr> {
+        let args = args.into_iter().map(|arg| arg.as_ref().to_os_string()).collect();
+
+        self.pre_process = Some((program.as_ref().to_os_string(), args));
+
+        self
+    }
+
+    /// Sets whether the audio is played in stereo, rather than asking
+    /// `ffprobe`.
+    ///
+    /// Inputs which can't be inspected are played in mono unless this is set.
+    pub fn stereo(mut self, stereo: bool) -> Self {
+        self.stereo = Some(stereo);
+
+        self
+    }
+
+    /// Sets how many lines written to stderr are kept, to be reported should
+    /// the process fail. Setting this to `0` discards stderr entirely.
+    ///
+    /// Defaults to `20`.
+    pub fn stderr_lines(mut self, lines: usize) -> Self {
+        self.stderr_lines = lines;
+
+        self
+    }
+
+    /// Sets metadata known about the input, to which anything found by
+    /// `ffprobe` is added.
+    ///
+    /// The input isn't probed at all if its channels and duration are both
+    /// known.
+    pub(crate) fn with_metadata(mut self, metadata: AudioMetadata) -> Self {
+        self.metadata = metadata.merge(self.metadata);
+
+        self
+    }
+
+    /// Starts the process, creating a seekable audio source.
+    ///
+    /// Seeking restarts the process, along with any pre-processor, at the new
+    /// position.
+    pub fn build(mut self) -> Result<Box<AudioSource>> {
+        // Probing a remote stream, such as one found by youtube-dl, takes a
+        // round trip which isn't needed if its details are already known.
+        let known = self.metadata.channels.is_some() && self.metadata.duration.is_some();
+
+        if self.pre_process.is_none() && !known {
+            // Will fail if the path is not to a file on the fs. Likely a
+            // YouTube URI.
+            match probe(&self.input) {
+                Ok(probed) => self.metadata = self.metadata.merge(probed),
+                Err(why) => debug!("[Voice] Error probing {:?}: {:?}", self.input, why),
+            }
+        }
+
+        let stereo = self.stereo.unwrap_or(self.metadata.channels == Some(2));
+        let child = self.spawn(stereo, None)?;
+
+        Ok(Box::new(ProcessSource {
+            inner: InputSource::new(stereo, child, AudioType::Pcm),
+            input: self,
+        }))
+    }
+
+    fn spawn(&self, stereo: bool, start: Option<Duration>) -> Result<ChildContainer> {
+        let stderr = if self.stderr_lines > 0 { Some(StderrTail::new(self.stderr_lines)) } else { None };
+
+        let mut pre = match self.pre_process {
+            Some((ref program, ref args)) => {
+                let mut child = Command::new(program)
+                    .args(args)
+                    .arg(&self.input)
+                    .stderr(pipe_if(stderr.is_some()))
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+
+                if let Some(ref stderr) = stderr {
+                    stderr.capture(child.stderr.take());
+                }
+
+                Some(child)
+            },
+            None => None,
+        };
+
+        let mut command = Command::new(&self.binary);
+
+        // Placing `-ss` before the input makes ffmpeg seek within the input,
+        // rather than decoding and discarding everything up to that point.
+        if let Some(start) = start {
+            command
+                .arg("-ss")
+                .arg(format!("{}.{:03}", start.as_secs(), start.subsec_nanos() / 1_000_000));
+        }
+
+        command.args(&self.input_args).arg("-i");
+
+        match pre.as_mut().and_then(|pre| pre.stdout.take()) {
+            Some(stdout) => {
+                command.arg("-").stdin(stdout);
+            },
+            None => {
+                command.arg(&self.input).stdin(Stdio::null());
+            },
+        }
+
+        let child = command
+            .args(&self.output_args)
+            .args(&[
+                "-f",
+                "s16le",
+                "-ac",
+                if stereo { "2" } else { "1" },
+                "-ar",
+                "48000",
+                "-acodec",
+                "pcm_s16le",
+                "-",
+            ])
+            .stderr(pipe_if(stderr.is_some()))
+            .stdout(Stdio::piped())
+            .spawn();
+
+        let mut child = match child {
+            Ok(child) => child,
+            Err(why) => {
+                if let Some(mut pre) = pre {
+                    let _ = pre.kill();
+                }
+
+                return Err(Error::from(why));
+            },
+        };
+
+        if let Some(ref stderr) = stderr {
+            stderr.capture(child.stderr.take());
+        }
+
+        Ok(ChildContainer {
+            child,
+            failed: false,
+            error: None,
+            exited: false,
+            pre,
+            stderr,
+        })
+    }
+}
+
+fn pipe_if(capture: bool) -> Stdio {
+    if capture { Stdio::piped() } else { Stdio::null() }
+}
+
+/// The last lines written to the stderr of one or more processes.
+struct StderrTail {
+    limit: usize,
+    lines: Arc<Mutex<VecDeque<String>>>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl StderrTail {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(limit))),
+            threads: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Reads a stream on a thread of its own until it closes.
+    fn capture<R: Read + Send + 'static>(&self, stream: Option<R>) {
+        let mut stream = match stream {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        let limit = self.limit;
+        let lines = Arc::clone(&self.lines);
+
+        let spawned = thread::Builder::new()
+            .name("Serenity Voice stderr".to_string())
+            .spawn(move || {
+                let mut buffer = [0u8; 1024];
+                let mut line = Vec::new();
+
+                loop {
+                    let len = match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(len) => len,
+                    };
+
+                    // Progress output is redrawn with carriage returns, so
+                    // those also end a line.
+                    for &byte in &buffer[..len] {
+                        if byte == b'\n' || byte == b'\r' {
+                            push_line(&lines, &mut line, limit);
+                        } else {
+                            line.push(byte);
+                        }
+                    }
+                }
+
+                push_line(&lines, &mut line, limit);
+            });
+
+        match spawned {
+            Ok(handle) => self.threads.lock().push(handle),
+            Err(why) => warn!("[Voice] Error starting stderr thread: {:?}", why),
+        }
+    }
+
+    /// Waits for the captured streams to close, returning their last lines.
+    fn finish(&self) -> String {
+        for handle in self.threads.lock().drain(..) {
+            let _ = handle.join();
+        }
+
+        let lines = self.lines.lock();
+
+        lines.iter().map(String::as_str).collect::<Vec<_>>().join("\n")
+    }
+}
+
+fn push_line(lines: &Mutex<VecDeque<String>>, line: &mut Vec<u8>, limit: usize) {
+    if line.is_empty() {
+        return;
+    }
+
+    let mut lines = lines.lock();
+
+    if lines.len() >= limit {
+        lines.pop_front();
+    }
+
+    lines.push_back(String::from_utf8_lossy(line).into_owned());
+    line.clear();
+}
+
+/// A running `ffmpeg` process, along with any pre-processor feeding it.
+///
+/// Reading past the end of `ffmpeg`'s output fails if `ffmpeg` exited
+/// unsuccessfully.
+struct ChildContainer {
+    child: Child,
+    error: Option<VoiceError>,
+    /// Whether the processes have been waited on.
+    exited: bool,
+    failed: bool,
+    pre: Option<Child>,
+    stderr: Option<StderrTail>,
+}
+
+impl ChildContainer {
+    /// Collects the exit status of `ffmpeg` once its output has ended,
+    /// returning an error if it failed.
+    ///
+    /// The pre-processor is killed rather than waited on, as it commonly exits
+    /// with an error once `ffmpeg` stops reading from it, such as after a `-t`
+    /// output option. Its status is only reported if `ffmpeg` also failed, as
+    /// the likely cause.
+    fn exit_error(&mut self) -> Option<VoiceError> {
+        let status = try_wait_briefly(&mut self.child);
+
+        if status.is_none() {
+            debug!("[Voice] ffmpeg still running after its output ended");
+        }
+
+        let pre_status = self.pre.as_mut().and_then(|pre| {
+            let status = pre.try_wait().unwrap_or(None);
+
+            if let Err(why) = pre.kill() {
+                debug!("[Voice] Error killing child process: {:?}", why);
+            }
+
+            status
+        });
+
+        let status = match status {
+            Some(status) if !status.success() => status,
+            _ => return None,
+        };
+
+        let status = match pre_status {
+            Some(pre_status) if !pre_status.success() => pre_status,
+            _ => status,
+        };
+
+        let stderr = self.stderr.as_ref().map(StderrTail::finish).unwrap_or_default();
+
+        Some(VoiceError::ProcessFailed(status, stderr))
+    }
+}
+
+/// Polls a process which is expected to exit imminently, such as after closing
+/// its output, giving up after a few milliseconds.
+fn try_wait_briefly(child: &mut Child) -> Option<ExitStatus> {
+    for _ in 0..10 {
+        match child.try_wait() {
+            Ok(Some(status)) => return Some(status),
+            Ok(None) => thread::sleep(Duration::from_millis(1)),
+            Err(why) => {
+                debug!("[Voice] Error awaiting child process: {:?}", why);
+
+                return None;
+            },
+        }
+    }
+
+    None
+}
+
+impl Read for ChildContainer {
+    fn read(&mut self, buffer: &mut [u8]) -> IoResult<usize> {
+        let len = if self.failed { 0 } else { self.child.stdout.as_mut().unwrap().read(buffer)? };
+
+        if len == 0 && !buffer.is_empty() {
+            if !self.exited {
+                self.exited = true;
+                self.error = self.exit_error();
+                self.failed = self.error.is_some();
+            }
+
+            if self.failed {
+                return Err(IoError::new(IoErrorKind::Other, "child process exited unsuccessfully"));
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+impl Drop for ChildContainer {
+    fn drop (&mut self) {
+        for child in Some(&mut self.child).into_iter().chain(self.pre.as_mut()) {
+            if let Err(e) = child.kill() {
+                debug!("[Voice] Error awaiting child process: {:?}", e);
+            }
+        }
+    }
+}
+
+/// An audio source which reads PCM from an `ffmpeg` child process.
+///
+/// Seeking is implemented by restarting the process at the new position.
+struct ProcessSource {
+    inner: InputSource<ChildContainer>,
+    input: ProcessInput,
+}
+
+impl AudioSource for ProcessSource {
+    fn is_stereo(&mut self) -> bool { self.inner.stereo }
+
+    fn get_type(&self) -> AudioType { self.inner.kind }
+
+    fn read_pcm_frame(&mut self, buffer: &mut [i16]) -> Option<usize> {
+        self.inner.read_pcm_frame(buffer)
+    }
+
+    fn read_opus_frame(&mut self) -> Option<Vec<u8>> { self.inner.read_opus_frame() }
+
+    fn is_seekable(&self) -> bool { true }
+
+    fn seek_time(&mut self, time: Duration) -> Option<Duration> {
+        match self.input.spawn(self.inner.stereo, Some(time)) {
+            Ok(child) => {
+                // The old process is killed as it is dropped.
+                self.inner.reader = child;
+
+                Some(time)
+            },
+            Err(why) => {
+                warn!("[Voice] Error restarting ffmpeg to seek: {:?}", why);
+
+                None
+            },
+        }
+    }
+
+    fn metadata(&self) -> AudioMetadata { self.input.metadata.clone() }
+
+    fn take_error(&mut self) -> Option<VoiceError> { self.inner.reader.error.take() }
+}
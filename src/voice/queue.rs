@@ -0,0 +1,357 @@
+use byteorder::{ByteOrder, LittleEndian};
+use parking_lot::Mutex;
+use sodiumoxide::randombytes;
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    mem,
+    sync::Arc,
+    time::Duration
+};
+use super::{Audio, AudioSource, LockedAudio};
+
+/// How a [`TrackQueue`] behaves once its current track finishes.
+///
+/// Looping requires the finished track's source to be seekable, as it is
+/// rewound to its start. Tracks which can't be rewound are dropped instead.
+///
+/// [`TrackQueue`]: struct.TrackQueue.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LoopMode {
+    /// Tracks are played once each, in order.
+    None,
+    /// The current track is repeated until it is skipped.
+    One,
+    /// Finished tracks are moved to the back of the queue.
+    All,
+}
+
+impl Default for LoopMode {
+    fn default() -> Self { LoopMode::None }
+}
+
+/// A point-in-time view of the contents of a [`TrackQueue`].
+///
+/// [`TrackQueue`]: struct.TrackQueue.html
+#[derive(Clone)]
+pub struct QueueSnapshot {
+    /// The track currently being played, if any.
+    pub current: Option<LockedAudio>,
+    /// Tracks waiting to be played, in order.
+    pub upcoming: Vec<LockedAudio>,
+    /// The active looping behaviour.
+    pub loop_mode: LoopMode,
+}
+
+impl QueueSnapshot {
+    /// The time left until the queue runs out, counting the rest of the
+    /// current track and all upcoming tracks.
+    ///
+    /// Tracks whose [`metadata`] has no duration are not counted.
+    ///
+    /// [`metadata`]: struct.Audio.html#structfield.metadata
+    pub fn remaining(&self) -> Duration {
+        let mut total = Duration::new(0, 0);
+
+        if let Some(ref track) = self.current {
+            let track = track.lock();
+
+            if let Some(duration) = track.metadata.duration {
+                total += duration.checked_sub(track.position).unwrap_or_else(|| Duration::new(0, 0));
+            }
+        }
+
+        for track in &self.upcoming {
+            if let Some(duration) = track.lock().metadata.duration {
+                total += duration;
+            }
+        }
+
+        total
+    }
+}
+
+#[derive(Default)]
+struct QueueCore {
+    crossfade: Option<Duration>,
+    current: Option<LockedAudio>,
+    upcoming: VecDeque<LockedAudio>,
+    loop_mode: LoopMode,
+    /// Set when the current track is to be ended by a skip, so that it is not
+    /// repeated under [`LoopMode::One`].
+    skipped: bool,
+    /// Tracks removed from the queue which the voice thread must stop.
+    to_stop: Vec<LockedAudio>,
+}
+
+/// A queue of tracks attached to a [`Handler`], played one after another.
+///
+/// Whenever the current track finishes, the voice thread automatically starts
+/// the next one. Tracks in the queue play alongside any started through
+/// [`Handler::play`].
+///
+/// The queue is cheap to clone: every clone refers to the same underlying
+/// queue.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use serenity::voice::{self, LoopMode};
+///
+/// let queue = handler.queue();
+///
+/// queue.enqueue(voice::ytdl("https://www.youtube.com/watch?v=dQw4w9WgXcQ")?);
+/// queue.enqueue(voice::ffmpeg("../audio/my-favourite-song.mp3")?);
+/// queue.set_loop_mode(LoopMode::All);
+///
+/// // Later on...
+/// queue.skip();
+/// ```
+///
+/// [`Handler`]: struct.Handler.html
+/// [`Handler::play`]: struct.Handler.html#method.play
+#[derive(Clone, Default)]
+pub struct TrackQueue {
+    inner: Arc<Mutex<QueueCore>>,
+}
+
+impl TrackQueue {
+    pub(crate) fn new() -> Self { Self::default() }
+
+    /// Adds a source to the back of the queue, returning its control handle.
+    pub fn enqueue(&self, source: Box<AudioSource>) -> LockedAudio {
+        let track = Arc::new(Mutex::new(Audio::new(source)));
+
+        self.enqueue_audio(Arc::clone(&track));
+
+        track
+    }
+
+    /// Adds an existing track to the back of the queue.
+    ///
+    /// The track should not also be playing through the [`Handler`].
+    ///
+    /// [`Handler`]: struct.Handler.html
+    pub fn enqueue_audio(&self, track: LockedAudio) {
+        self.inner.lock().upcoming.push_back(track);
+    }
+
+    /// Stops the current track, moving on to the next one.
+    ///
+    /// Under [`LoopMode::All`], the skipped track is moved to the back of the
+    /// queue. If a crossfade has been set through [`set_crossfade`], the two
+    /// tracks are faded between.
+    ///
+    /// [`LoopMode::All`]: enum.LoopMode.html#variant.All
+    /// [`set_crossfade`]: #method.set_crossfade
+    pub fn skip(&self) { self.inner.lock().skipped = true; }
+
+    /// Removes the track at the given index, where index `0` is the current
+    /// track.
+    ///
+    /// Removing the current track ends it, as though it had been skipped,
+    /// without it being requeued.
+    pub fn remove_at(&self, index: usize) -> Option<LockedAudio> {
+        let mut core = self.inner.lock();
+
+        if index == 0 {
+            let current = core.current.take();
+
+            if let Some(ref track) = current {
+                core.to_stop.push(Arc::clone(track));
+            }
+
+            current
+        } else {
+            core.upcoming.remove(index - 1)
+        }
+    }
+
+    /// Randomly reorders the tracks waiting to be played.
+    ///
+    /// The current track is unaffected.
+    pub fn shuffle(&self) {
+        let mut core = self.inner.lock();
+        let upcoming = &mut core.upcoming;
+
+        // Fisher-Yates.
+        for i in (1..upcoming.len()).rev() {
+            let j = random_below(i + 1);
+
+            upcoming.swap(i, j);
+        }
+    }
+
+    /// Stops the current track and removes every track from the queue.
+    pub fn clear(&self) {
+        let mut core = self.inner.lock();
+
+        if let Some(track) = core.current.take() {
+            core.to_stop.push(track);
+        }
+
+        core.upcoming.clear();
+    }
+
+    /// Sets how long [`skip`] spends fading between the current track and the
+    /// next, or `None` to cut between them immediately.
+    ///
+    /// Tracks which are crossfaded away from are not requeued under
+    /// [`LoopMode::All`].
+    ///
+    /// [`LoopMode::All`]: enum.LoopMode.html#variant.All
+    /// [`skip`]: #method.skip
+    pub fn set_crossfade(&self, crossfade: Option<Duration>) {
+        self.inner.lock().crossfade = crossfade;
+    }
+
+    /// Sets how the queue behaves once the current track finishes.
+    pub fn set_loop_mode(&self, loop_mode: LoopMode) { self.inner.lock().loop_mode = loop_mode; }
+
+    /// The track currently being played, if any.
+    pub fn current(&self) -> Option<LockedAudio> { self.inner.lock().current.clone() }
+
+    /// The number of tracks in the queue, including the current track.
+    pub fn len(&self) -> usize {
+        let core = self.inner.lock();
+
+        core.upcoming.len() + if core.current.is_some() { 1 } else { 0 }
+    }
+
+    /// Whether there are no tracks playing or waiting in the queue.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Returns a copy of the queue's current state.
+    pub fn snapshot(&self) -> QueueSnapshot {
+        let core = self.inner.lock();
+
+        QueueSnapshot {
+            current: core.current.clone(),
+            upcoming: core.upcoming.iter().cloned().collect(),
+            loop_mode: core.loop_mode,
+        }
+    }
+
+    /// Starts the next track if the current one has finished, adding it to the
+    /// voice thread's active `senders`.
+    ///
+    /// *Used internally* by the voice thread, after finished tracks have been
+    /// removed.
+    pub(crate) fn advance(&self, senders: &mut Vec<LockedAudio>) {
+        // Tracks are never locked while the queue is, so that a track's lock
+        // may be held while calling into the queue without deadlocking.
+        let (current, loop_mode, skipped, to_stop, crossfade) = {
+            let mut core = self.inner.lock();
+            let to_stop = mem::replace(&mut core.to_stop, Vec::new());
+
+            (core.current.clone(), core.loop_mode, core.skipped, to_stop, core.crossfade)
+        };
+
+        for track in &to_stop {
+            track.lock().finished = true;
+        }
+
+        let mut requeue = None;
+        let mut fade_in = None;
+
+        if let Some(ref track) = current {
+            {
+                let mut aud = track.lock();
+
+                if !aud.finished {
+                    if !skipped {
+                        return;
+                    }
+
+                    match crossfade {
+                        // The skipped track fades out and stops by itself,
+                        // while the next one fades in alongside it.
+                        Some(duration) => {
+                            aud.fade_out(duration);
+                            fade_in = Some(duration);
+                        },
+                        // A skipped track is stopped and cleaned up by the
+                        // voice thread over the next frame, before it is
+                        // replaced.
+                        None => {
+                            aud.finished = true;
+
+                            return;
+                        },
+                    }
+                }
+            }
+
+            match loop_mode {
+                _ if fade_in.is_some() => {},
+                LoopMode::One if !skipped && rewind(track) => {
+                    senders.push(Arc::clone(track));
+
+                    return;
+                },
+                LoopMode::All if rewind(track) => requeue = Some(Arc::clone(track)),
+                _ => {},
+            }
+        }
+
+        let mut core = self.inner.lock();
+        core.skipped = false;
+
+        if let Some(track) = current {
+            // The current track may have been removed in the meantime.
+            let unchanged = core.current
+                .as_ref()
+                .map_or(false, |c| Arc::ptr_eq(c, &track));
+
+            if unchanged {
+                core.current = None;
+
+                if let Some(track) = requeue {
+                    core.upcoming.push_back(track);
+                }
+            }
+        }
+
+        let mut started = None;
+
+        if core.current.is_none() {
+            if let Some(next) = core.upcoming.pop_front() {
+                senders.push(Arc::clone(&next));
+                started = Some(Arc::clone(&next));
+                core.current = Some(next);
+            }
+        }
+
+        drop(core);
+
+        if let (Some(track), Some(duration)) = (started, fade_in) {
+            track.lock().fade_in(duration);
+        }
+    }
+}
+
+impl Debug for TrackQueue {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("TrackQueue")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Resets a finished track to play again from the start.
+fn rewind(track: &LockedAudio) -> bool {
+    if track.lock().rewind() {
+        return true;
+    }
+
+    warn!("[Voice] Unable to loop a track whose source can't seek; dropping it.");
+
+    false
+}
+
+fn random_below(bound: usize) -> usize {
+    let mut bytes = [0u8; 8];
+    randombytes::randombytes_into(&mut bytes);
+
+    (LittleEndian::read_u64(&bytes) % bound as u64) as usize
+}
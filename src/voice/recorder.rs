@@ -0,0 +1,534 @@
+use internal::prelude::*;
+use model::id::UserId;
+use opus::{
+    packet as opus_packet,
+    Application as CodingMode,
+    Channels,
+    Encoder as OpusEncoder,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::BufWriter,
+    path::PathBuf,
+    time::Instant
+};
+use super::audio::{duration_to_frames, AudioReceiver, FRAME_SAMPLES, SAMPLE_RATE, SILENT_FRAME};
+use super::ogg::OggOpusWriter;
+use super::wav::WavWriter;
+
+/// Number of frames the mixdown holds in memory, so that speakers whose audio
+/// arrives slightly later than others' can still be mixed in.
+const MIX_DELAY: u64 = 10;
+
+/// Longest gap in a speaker's audio, in samples per channel, which is filled
+/// with silence. Anything longer is taken to be a jump in their timestamps,
+/// and recording carries on from the new timestamp without a gap.
+const MAX_GAP: usize = SAMPLE_RATE as usize * 60 * 10;
+
+/// The file format written by a [`Recorder`].
+///
+/// [`Recorder`]: struct.Recorder.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecordingFormat {
+    /// Decoded 16-bit PCM, stored in a WAV file.
+    Wav,
+    /// The received Opus packets, remuxed without re-encoding into an Ogg
+    /// file.
+    OggOpus,
+}
+
+impl RecordingFormat {
+    fn extension(&self) -> &'static str {
+        match *self {
+            RecordingFormat::Wav => "wav",
+            RecordingFormat::OggOpus => "ogg",
+        }
+    }
+}
+
+enum TrackWriter {
+    Wav(WavWriter<BufWriter<File>>),
+    Ogg(OggOpusWriter<BufWriter<File>>),
+    /// Writing to the file failed, so it is no longer recorded to.
+    Failed,
+}
+
+impl TrackWriter {
+    fn create(path: PathBuf, format: RecordingFormat) -> Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+
+        Ok(match format {
+            RecordingFormat::Wav => TrackWriter::Wav(WavWriter::new(file, 2)?),
+            RecordingFormat::OggOpus => TrackWriter::Ogg(OggOpusWriter::new(file, 2, 0)?),
+        })
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match *self {
+            TrackWriter::Wav(ref mut wav) => wav.finish(),
+            TrackWriter::Ogg(ref mut ogg) => ogg.finish(),
+            TrackWriter::Failed => Ok(()),
+        }
+    }
+}
+
+/// The recording of a single speaker.
+struct UserTrack {
+    /// RTP timestamp at which the next frame should begin, used to find gaps in
+    /// the received audio.
+    next_timestamp: Option<u32>,
+    path: PathBuf,
+    /// The speaker, if known when the file was named.
+    user: Option<UserId>,
+    writer: TrackWriter,
+}
+
+impl UserTrack {
+    /// Returns the number of samples (per channel) missing before a frame with
+    /// the given timestamp, and moves past that frame.
+    fn gap_before(&mut self, timestamp: u32, samples: usize) -> usize {
+        let gap = self.next_timestamp
+            .map_or(0, |next| timestamp.wrapping_sub(next) as i32);
+
+        self.next_timestamp = Some(timestamp.wrapping_add(samples as u32));
+
+        match gap {
+            gap if gap <= 0 => 0,
+            gap if gap as usize > MAX_GAP => {
+                debug!("[Voice] Ignoring a jump of {} samples in a recording", gap);
+
+                0
+            },
+            gap => gap as usize,
+        }
+    }
+
+    fn write_pcm(&mut self, timestamp: u32, stereo: bool, data: &[i16]) -> Result<()> {
+        let samples = if stereo { data.len() / 2 } else { data.len() };
+
+        if let TrackWriter::Wav(_) = self.writer {
+            let gap = self.gap_before(timestamp, samples);
+
+            if let TrackWriter::Wav(ref mut wav) = self.writer {
+                wav.write_silence(gap)?;
+                wav.write_samples(&to_stereo(stereo, data))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_opus(&mut self, timestamp: u32, data: &[u8]) -> Result<()> {
+        if let TrackWriter::Ogg(_) = self.writer {
+            let samples = opus_packet::get_nb_samples(data, SAMPLE_RATE)?;
+            let gap = self.gap_before(timestamp, samples);
+
+            if let TrackWriter::Ogg(ref mut ogg) = self.writer {
+                for _ in 0..gap / FRAME_SAMPLES {
+                    ogg.write_packet(&SILENT_FRAME, FRAME_SAMPLES as u64)?;
+                }
+
+                ogg.write_packet(data, samples as u64)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Where a speaker's RTP timestamps fall on the mixdown's timeline.
+struct Anchor {
+    frame: u64,
+    timestamp: u32,
+    next_timestamp: u32,
+}
+
+/// A single track combining the audio of every speaker.
+struct Mixdown {
+    anchors: HashMap<u32, Anchor>,
+    /// Index of the first frame in `pending`.
+    base: u64,
+    encoder: Option<OpusEncoder>,
+    pending: VecDeque<[i32; FRAME_SAMPLES * 2]>,
+    start: Instant,
+    writer: TrackWriter,
+}
+
+impl Mixdown {
+    fn new(path: PathBuf, format: RecordingFormat) -> Result<Self> {
+        let encoder = match format {
+            RecordingFormat::OggOpus => {
+                Some(OpusEncoder::new(SAMPLE_RATE, Channels::Stereo, CodingMode::Audio)?)
+            },
+            RecordingFormat::Wav => None,
+        };
+
+        Ok(Self {
+            anchors: HashMap::new(),
+            base: 0,
+            encoder,
+            pending: VecDeque::new(),
+            start: Instant::now(),
+            writer: TrackWriter::create(path, format)?,
+        })
+    }
+
+    fn add(&mut self, ssrc: u32, timestamp: u32, stereo: bool, data: &[i16]) -> Result<()> {
+        let samples = if stereo { data.len() / 2 } else { data.len() };
+        let now = duration_to_frames(self.start.elapsed());
+
+        // Speakers' clocks are unrelated, so each stretch of continuous audio
+        // is placed according to when it arrived.
+        let anchor = self.anchors.entry(ssrc).or_insert(Anchor {
+            frame: now,
+            timestamp,
+            next_timestamp: timestamp,
+        });
+
+        if anchor.next_timestamp != timestamp {
+            anchor.frame = now;
+            anchor.timestamp = timestamp;
+        }
+
+        anchor.next_timestamp = timestamp.wrapping_add(samples as u32);
+
+        let offset = timestamp.wrapping_sub(anchor.timestamp) as usize / FRAME_SAMPLES;
+        let index = anchor.frame + offset as u64;
+
+        if index < self.base {
+            debug!("[Voice] Audio from {} arrived too late to mix.", ssrc);
+
+            return Ok(());
+        }
+
+        while index >= self.base + MIX_DELAY {
+            let frame = self.pending.pop_front().unwrap_or([0; FRAME_SAMPLES * 2]);
+
+            self.write_frame(&frame)?;
+            self.base += 1;
+        }
+
+        let slot = (index - self.base) as usize;
+
+        while self.pending.len() <= slot {
+            self.pending.push_back([0; FRAME_SAMPLES * 2]);
+        }
+
+        let frame = &mut self.pending[slot];
+
+        for (mixed, sample) in frame.iter_mut().zip(to_stereo(stereo, data)) {
+            *mixed += i32::from(sample);
+        }
+
+        Ok(())
+    }
+
+    fn write_frame(&mut self, frame: &[i32; FRAME_SAMPLES * 2]) -> Result<()> {
+        let mut samples = [0i16; FRAME_SAMPLES * 2];
+
+        for (out, &mixed) in samples.iter_mut().zip(frame.iter()) {
+            *out = mixed.max(i32::from(i16::min_value())).min(i32::from(i16::max_value())) as i16;
+        }
+
+        match self.writer {
+            TrackWriter::Wav(ref mut wav) => wav.write_samples(&samples),
+            TrackWriter::Ogg(ref mut ogg) => {
+                if samples.iter().all(|&s| s == 0) {
+                    return ogg.write_packet(&SILENT_FRAME, FRAME_SAMPLES as u64);
+                }
+
+                let mut packet = [0u8; 4000];
+                let len = match self.encoder {
+                    Some(ref mut encoder) => encoder.encode(&samples, &mut packet)?,
+                    None => return Ok(()),
+                };
+
+                ogg.write_packet(&packet[..len], FRAME_SAMPLES as u64)
+            },
+            TrackWriter::Failed => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        while let Some(frame) = self.pending.pop_front() {
+            self.write_frame(&frame)?;
+        }
+
+        self.writer.finish()
+    }
+}
+
+/// An [`AudioReceiver`] which records each speaker in a voice channel to their
+/// own file, and optionally records a mixdown of every speaker.
+///
+/// Files are written into the given directory, named after each speaker's
+/// user ID and SSRC. A speaker whose user is not yet known is recorded under
+/// `unknown`, and their file is renamed once Discord says who they are. Gaps
+/// in a speaker's audio are filled with silence. A speaker's file is completed
+/// when they leave the channel or the connection moves to another voice
+/// server, and the rest once the recorder is dropped, such as when it is
+/// replaced via [`Handler::listen`].
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use serenity::voice::{Recorder, RecordingFormat};
+///
+/// let recorder = Recorder::new("recordings/meeting", RecordingFormat::Wav)?
+///     .mixdown(true);
+///
+/// handler.listen(Some(Box::new(recorder)));
+/// ```
+///
+/// [`AudioReceiver`]: trait.AudioReceiver.html
+/// [`Handler::listen`]: struct.Handler.html#method.listen
+pub struct Recorder {
+    directory: PathBuf,
+    format: RecordingFormat,
+    mixdown: Option<Mixdown>,
+    mixdown_enabled: bool,
+    tracks: HashMap<u32, UserTrack>,
+    users: HashMap<u32, UserId>,
+}
+
+impl Recorder {
+    /// Creates a recorder writing files of the given format into `directory`,
+    /// creating the directory if it does not exist.
+    pub fn new<P: Into<PathBuf>>(directory: P, format: RecordingFormat) -> Result<Self> {
+        let directory = directory.into();
+
+        fs::create_dir_all(&directory)?;
+
+        Ok(Self {
+            directory,
+            format,
+            mixdown: None,
+            mixdown_enabled: false,
+            tracks: HashMap::new(),
+            users: HashMap::new(),
+        })
+    }
+
+    /// Sets whether a single track combining every speaker is also recorded,
+    /// to a file named `mixdown`.
+    ///
+    /// Under [`RecordingFormat::OggOpus`], the mixdown is re-encoded.
+    ///
+    /// [`RecordingFormat::OggOpus`]: enum.RecordingFormat.html#variant.OggOpus
+    pub fn mixdown(mut self, enabled: bool) -> Self {
+        self.mixdown_enabled = enabled;
+
+        self
+    }
+
+    fn track_path(&self, ssrc: u32, user: Option<UserId>) -> PathBuf {
+        let user = user.map_or_else(|| "unknown".to_owned(), |id| id.0.to_string());
+        let extension = self.format.extension();
+        let mut path = self.directory.join(format!("{}-{}.{}", user, ssrc, extension));
+
+        // A user can be given an SSRC they had before, such as after moving
+        // server, so earlier recordings are kept rather than overwritten.
+        let mut n = 1;
+
+        while path.exists() {
+            n += 1;
+            path = self.directory.join(format!("{}-{}-{}.{}", user, ssrc, n, extension));
+        }
+
+        path
+    }
+
+    fn track(&mut self, ssrc: u32) -> &mut UserTrack {
+        if !self.tracks.contains_key(&ssrc) {
+            let user = self.users.get(&ssrc).cloned();
+            let path = self.track_path(ssrc, user);
+
+            let writer = TrackWriter::create(path.clone(), self.format).unwrap_or_else(|why| {
+                warn!("[Voice] Failed to create recording for {}: {:?}", ssrc, why);
+
+                TrackWriter::Failed
+            });
+
+            self.tracks.insert(ssrc, UserTrack {
+                next_timestamp: None,
+                path,
+                user,
+                writer,
+            });
+        }
+
+        // Safe, as the track was inserted above.
+        self.tracks.get_mut(&ssrc).unwrap()
+    }
+
+    /// Maps an SSRC to its user, renaming any recording which was started
+    /// before the user was known.
+    fn identify(&mut self, ssrc: u32, user_id: UserId) {
+        self.users.insert(ssrc, user_id);
+
+        match self.tracks.get(&ssrc).map(|track| track.user) {
+            Some(None) => {},
+            // The SSRC has been given to someone else, whose audio belongs in
+            // a recording of their own.
+            Some(Some(user)) if user != user_id => {
+                self.finish_track(ssrc);
+
+                return;
+            },
+            _ => return,
+        }
+
+        let path = self.track_path(ssrc, Some(user_id));
+
+        // Safe, as the track was found above.
+        let track = self.tracks.get_mut(&ssrc).unwrap();
+
+        track.user = Some(user_id);
+
+        if let TrackWriter::Failed = track.writer {
+            return;
+        }
+
+        match fs::rename(&track.path, &path) {
+            Ok(()) => track.path = path,
+            Err(why) => warn!("[Voice] Failed to rename recording for {}: {:?}", ssrc, why),
+        }
+    }
+
+    /// Completes the recording of an SSRC which won't be heard from again,
+    /// so that any later audio from it starts a new recording.
+    fn finish_track(&mut self, ssrc: u32) {
+        if let Some(mut track) = self.tracks.remove(&ssrc) {
+            if let Err(why) = track.writer.finish() {
+                warn!("[Voice] Failed to finish recording for {}: {:?}", ssrc, why);
+            }
+        }
+
+        if let Some(ref mut mixdown) = self.mixdown {
+            mixdown.anchors.remove(&ssrc);
+        }
+    }
+
+    fn mix(&mut self, ssrc: u32, timestamp: u32, stereo: bool, data: &[i16]) {
+        if !self.mixdown_enabled {
+            return;
+        }
+
+        if self.mixdown.is_none() {
+            let path = self.directory.join(format!("mixdown.{}", self.format.extension()));
+
+            match Mixdown::new(path, self.format) {
+                Ok(mixdown) => self.mixdown = Some(mixdown),
+                Err(why) => {
+                    warn!("[Voice] Failed to create mixdown recording: {:?}", why);
+
+                    self.mixdown_enabled = false;
+
+                    return;
+                },
+            }
+        }
+
+        if let Some(ref mut mixdown) = self.mixdown {
+            if let Err(why) = mixdown.add(ssrc, timestamp, stereo, data) {
+                warn!("[Voice] Failed to write mixdown recording: {:?}", why);
+
+                mixdown.writer = TrackWriter::Failed;
+            }
+        }
+    }
+}
+
+impl AudioReceiver for Recorder {
+    fn speaking_update(&mut self, ssrc: u32, user_id: u64, _speaking: bool) {
+        self.identify(ssrc, UserId(user_id));
+    }
+
+    fn client_connect(&mut self, ssrc: u32, user_id: u64) {
+        self.identify(ssrc, UserId(user_id));
+    }
+
+    fn client_disconnect(&mut self, user_id: u64) {
+        let ssrcs = self.users
+            .iter()
+            .filter(|&(_, id)| id.0 == user_id)
+            .map(|(&ssrc, _)| ssrc)
+            .collect::<Vec<_>>();
+
+        for ssrc in ssrcs {
+            self.finish_track(ssrc);
+            self.users.remove(&ssrc);
+        }
+    }
+
+    fn region_changed(&mut self, _old_endpoint: &str, _new_endpoint: &str) {
+        // None of the old SSRCs will be heard from again.
+        let ssrcs = self.tracks.keys().cloned().collect::<Vec<_>>();
+
+        for ssrc in ssrcs {
+            self.finish_track(ssrc);
+        }
+
+        self.users.clear();
+    }
+
+    fn voice_packet(&mut self,
+                    ssrc: u32,
+                    _sequence: u16,
+                    timestamp: u32,
+                    stereo: bool,
+                    data: &[i16]) {
+        self.mix(ssrc, timestamp, stereo, data);
+
+        let track = self.track(ssrc);
+
+        if let Err(why) = track.write_pcm(timestamp, stereo, data) {
+            warn!("[Voice] Failed to write recording for {}: {:?}", ssrc, why);
+
+            track.writer = TrackWriter::Failed;
+        }
+    }
+
+    fn opus_packet(&mut self, ssrc: u32, _sequence: u16, timestamp: u32, data: &[u8]) {
+        let track = self.track(ssrc);
+
+        if let Err(why) = track.write_opus(timestamp, data) {
+            warn!("[Voice] Failed to write recording for {}: {:?}", ssrc, why);
+
+            track.writer = TrackWriter::Failed;
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        for (ssrc, track) in &mut self.tracks {
+            if let Err(why) = track.writer.finish() {
+                warn!("[Voice] Failed to finish recording for {}: {:?}", ssrc, why);
+            }
+        }
+
+        if let Some(ref mut mixdown) = self.mixdown {
+            if let Err(why) = mixdown.finish() {
+                warn!("[Voice] Failed to finish mixdown recording: {:?}", why);
+            }
+        }
+    }
+}
+
+/// Copies a frame of samples, duplicating each sample of mono audio.
+fn to_stereo(stereo: bool, data: &[i16]) -> Vec<i16> {
+    if stereo {
+        return data.to_vec();
+    }
+
+    let mut out = Vec::with_capacity(data.len() * 2);
+
+    for &sample in data {
+        out.push(sample);
+        out.push(sample);
+    }
+
+    out
+}
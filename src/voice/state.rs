@@ -0,0 +1,229 @@
+use parking_lot::{Condvar, Mutex};
+use std::{
+    result::Result as StdResult,
+    sync::{
+        mpsc::{self, Receiver as MpscReceiver, Sender as MpscSender},
+        Arc
+    },
+    time::{Duration, Instant}
+};
+use super::VoiceError;
+
+/// The state of a [`Handler`]'s voice connection, retrieved through
+/// [`Handler::state`] or followed through [`Handler::subscribe`].
+///
+/// [`Handler`]: struct.Handler.html
+/// [`Handler::state`]: struct.Handler.html#method.state
+/// [`Handler::subscribe`]: struct.Handler.html#method.subscribe
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionState {
+    /// There is no connection to a voice server.
+    Disconnected,
+    /// A connection is being established, such as after joining a channel or
+    /// being moved to a new voice server.
+    Connecting,
+    /// The connection is ready to send and receive audio.
+    Connected,
+    /// The connection was lost, and is being resumed or re-established.
+    Reconnecting,
+    /// An attempt to connect failed. This is followed by
+    /// [`Disconnected`].
+    ///
+    /// [`Disconnected`]: #variant.Disconnected
+    Failed(VoiceError),
+}
+
+/// A channel join which has been requested, whose outcome can be waited on.
+///
+/// Created by [`Handler::join_pending`] and [`Manager::join_pending`].
+///
+/// The connection is completed by voice events from the gateway, which are
+/// passed to the [`Manager`] by the shard. The manager's lock must therefore
+/// be released before calling [`wait`].
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use std::time::Duration;
+///
+/// let pending = manager.lock().join_pending(guild_id, channel_id);
+///
+/// match pending.wait(Duration::from_secs(10)) {
+///     Ok(()) => println!("Joined the channel"),
+///     Err(why) => println!("Error joining the channel: {:?}", why),
+/// }
+/// ```
+///
+/// [`Handler::join_pending`]: struct.Handler.html#method.join_pending
+/// [`Manager`]: struct.Manager.html
+/// [`Manager::join_pending`]: struct.Manager.html#method.join_pending
+/// [`wait`]: #method.wait
+#[derive(Clone, Debug)]
+pub struct PendingJoin {
+    /// The number of state changes seen before the join was made, or `None`
+    /// if the channel was already connected to.
+    generation: Option<u64>,
+    state: SharedState,
+}
+
+impl PendingJoin {
+    pub(crate) fn new(state: SharedState, connected: bool) -> Self {
+        let generation = if connected { None } else { Some(state.inner.0.lock().generation) };
+
+        Self {
+            generation,
+            state,
+        }
+    }
+
+    /// Blocks until the connection is established or fails, for at most
+    /// `timeout`.
+    ///
+    /// Returns [`VoiceError::Timeout`] if no connection was made in time,
+    /// which is most often because the current user lacks permission to
+    /// connect to the channel.
+    ///
+    /// [`VoiceError::Timeout`]: enum.VoiceError.html#variant.Timeout
+    pub fn wait(self, timeout: Duration) -> StdResult<(), VoiceError> {
+        let generation = match self.generation {
+            Some(generation) => generation,
+            None => return Ok(()),
+        };
+
+        let deadline = Instant::now() + timeout;
+        let (ref lock, ref condvar) = *self.state.inner;
+        let mut inner = lock.lock();
+
+        loop {
+            if let Some((at, ref outcome)) = inner.outcome {
+                if at > generation {
+                    return outcome.clone();
+                }
+            }
+
+            if condvar.wait_until(&mut inner, deadline).timed_out() {
+                return Err(VoiceError::Timeout);
+            }
+        }
+    }
+}
+
+/// The connection state of a handler, shared with its voice thread.
+#[derive(Clone, Debug)]
+pub(crate) struct SharedState {
+    inner: Arc<(Mutex<StateInner>, Condvar)>,
+}
+
+#[derive(Debug)]
+struct StateInner {
+    /// The number of state changes so far.
+    generation: u64,
+    /// The result of the latest attempt to connect, and the generation at
+    /// which it happened.
+    outcome: Option<(u64, StdResult<(), VoiceError>)>,
+    state: ConnectionState,
+    subscribers: Vec<MpscSender<ConnectionState>>,
+}
+
+impl SharedState {
+    pub(crate) fn get(&self) -> ConnectionState { self.inner.0.lock().state.clone() }
+
+    pub(crate) fn set(&self, state: ConnectionState) {
+        let (ref lock, ref condvar) = *self.inner;
+        let mut inner = lock.lock();
+
+        if inner.state == state {
+            return;
+        }
+
+        inner.generation += 1;
+
+        match state {
+            ConnectionState::Connected => inner.outcome = Some((inner.generation, Ok(()))),
+            ConnectionState::Failed(ref why) => {
+                inner.outcome = Some((inner.generation, Err(why.clone())));
+            },
+            _ => {},
+        }
+
+        // Subscribers which have gone away are forgotten.
+        inner.subscribers.retain(|subscriber| subscriber.send(state.clone()).is_ok());
+        inner.state = state;
+
+        condvar.notify_all();
+    }
+
+    pub(crate) fn subscribe(&self) -> MpscReceiver<ConnectionState> {
+        let (tx, rx) = mpsc::channel();
+
+        self.inner.0.lock().subscribers.push(tx);
+
+        rx
+    }
+}
+
+impl Default for SharedState {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new((Mutex::new(StateInner {
+                generation: 0,
+                outcome: None,
+                state: ConnectionState::Disconnected,
+                subscribers: Vec::new(),
+            }), Condvar::new())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn join_when_connected_is_complete() {
+        let state = SharedState::default();
+
+        assert_eq!(PendingJoin::new(state, true).wait(Duration::from_millis(0)), Ok(()));
+    }
+
+    #[test]
+    fn join_waits_for_connection() {
+        let state = SharedState::default();
+        let pending = PendingJoin::new(state.clone(), false);
+
+        let setter = thread::spawn(move || {
+            state.set(ConnectionState::Connecting);
+            thread::sleep(Duration::from_millis(20));
+            state.set(ConnectionState::Connected);
+        });
+
+        assert_eq!(pending.wait(Duration::from_secs(5)), Ok(()));
+        setter.join().unwrap();
+    }
+
+    #[test]
+    fn join_failure_is_returned() {
+        let state = SharedState::default();
+        let pending = PendingJoin::new(state.clone(), false);
+
+        state.set(ConnectionState::Connecting);
+        state.set(ConnectionState::Failed(VoiceError::VoiceModeUnavailable));
+        state.set(ConnectionState::Disconnected);
+
+        assert_eq!(pending.wait(Duration::from_secs(5)), Err(VoiceError::VoiceModeUnavailable));
+    }
+
+    #[test]
+    fn earlier_outcome_is_ignored() {
+        let state = SharedState::default();
+
+        state.set(ConnectionState::Connected);
+        state.set(ConnectionState::Disconnected);
+
+        let pending = PendingJoin::new(state.clone(), false);
+        state.set(ConnectionState::Connecting);
+
+        assert_eq!(pending.wait(Duration::from_millis(20)), Err(VoiceError::Timeout));
+    }
+}
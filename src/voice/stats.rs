@@ -0,0 +1,141 @@
+use internal::{nanos, Tick};
+use std::{
+    collections::HashMap,
+    time::Duration
+};
+
+/// A snapshot of the statistics of a voice connection, retrieved through
+/// [`Handler::stats`].
+///
+/// Counters cover the current connection only, and start again from zero if
+/// it has to be re-established from scratch.
+///
+/// [`Handler::stats`]: struct.Handler.html#method.stats
+#[derive(Clone, Debug, Default)]
+pub struct VoiceStats {
+    /// The number of audio packets sent, including silence frames.
+    pub packets_sent: u64,
+    /// The total size of all audio packets sent, in bytes.
+    pub bytes_sent: u64,
+    /// The RTP sequence number which will be given to the next packet sent.
+    pub sequence: u16,
+    /// The RTP timestamp which will be given to the next packet sent.
+    pub timestamp: u32,
+    /// Statistics for the audio received from each other user, keyed by
+    /// their SSRC.
+    pub received: HashMap<u32, ReceivedStats>,
+    /// The round-trip time of the most recently acknowledged websocket
+    /// heartbeat.
+    pub rtt: Option<Duration>,
+    /// The time spent encoding the most recent frame of mixed audio.
+    ///
+    /// Opus frames sent without being re-encoded are not counted.
+    pub encode_time: Option<Duration>,
+    /// The number of frames of mixed audio which have been encoded.
+    pub frames_encoded: u64,
+    /// The total time spent encoding frames of mixed audio.
+    pub total_encode_time: Duration,
+    /// The number of frames which were sent later than they were due, such
+    /// as when mixing took longer than 20ms.
+    pub late_ticks: u64,
+    /// The number of frames which were never sent, as the voice thread had
+    /// fallen so far behind that sending them would have caused a burst of
+    /// audio.
+    pub skipped_ticks: u64,
+}
+
+impl VoiceStats {
+    /// The average time spent encoding each frame of mixed audio, if any have
+    /// been encoded.
+    pub fn mean_encode_time(&self) -> Option<Duration> {
+        if self.frames_encoded == 0 {
+            return None;
+        }
+
+        let nanos = nanos(self.total_encode_time) / self.frames_encoded;
+
+        Some(Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32))
+    }
+
+    pub(crate) fn record_sent(&mut self, bytes: usize, sequence: u16, timestamp: u32) {
+        self.packets_sent += 1;
+        self.bytes_sent += bytes as u64;
+        self.sequence = sequence;
+        self.timestamp = timestamp;
+    }
+
+    pub(crate) fn record_received(&mut self, ssrc: u32, sequence: u16) {
+        self.received
+            .entry(ssrc)
+            .or_default()
+            .record(sequence);
+    }
+
+    pub(crate) fn record_tick(&mut self, tick: Tick) {
+        match tick {
+            Tick::OnTime => {},
+            Tick::Late => self.late_ticks += 1,
+            Tick::Skipped(ticks) => {
+                self.late_ticks += 1;
+                self.skipped_ticks += u64::from(ticks);
+            },
+        }
+    }
+
+    pub(crate) fn record_encode(&mut self, time: Duration) {
+        self.encode_time = Some(time);
+        self.frames_encoded += 1;
+        self.total_encode_time += time;
+    }
+}
+
+/// Statistics for the audio received from a single user.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReceivedStats {
+    /// The number of packets received.
+    pub packets: u64,
+    /// The estimated number of packets lost, based on gaps in their sequence
+    /// numbers.
+    ///
+    /// Packets which arrive late are removed from this count once they
+    /// arrive.
+    pub lost: u64,
+    highest_sequence: Option<u16>,
+}
+
+impl ReceivedStats {
+    /// The estimated fraction of packets lost, from `0.0` to `1.0`.
+    pub fn loss(&self) -> f32 {
+        let expected = self.packets + self.lost;
+
+        if expected == 0 {
+            0.0
+        } else {
+            self.lost as f32 / expected as f32
+        }
+    }
+
+    fn record(&mut self, sequence: u16) {
+        self.packets += 1;
+
+        let highest = match self.highest_sequence {
+            Some(highest) => highest,
+            None => {
+                self.highest_sequence = Some(sequence);
+
+                return;
+            },
+        };
+
+        // Sequence numbers wrap, so compare them by their signed distance.
+        let distance = sequence.wrapping_sub(highest) as i16;
+
+        if distance > 0 {
+            self.lost += (distance - 1) as u64;
+            self.highest_sequence = Some(sequence);
+        } else if distance < 0 && self.lost > 0 {
+            // A late packet fills in a gap counted as lost.
+            self.lost -= 1;
+        }
+    }
+}
@@ -1,5 +1,10 @@
 use byteorder::{LittleEndian, ReadBytesExt};
 use internal::prelude::*;
+use opus::{
+    packet as opus_packet,
+    Channels,
+    Decoder as OpusDecoder
+};
 use serde_json;
 use std::{
     ffi::OsStr,
@@ -8,45 +13,45 @@ use std::{
         BufReader, 
         ErrorKind as IoErrorKind, 
         Read, 
-        Result as IoResult
-    },
-    process::{
-        Child, 
-        Command, 
-        Stdio
+        Result as IoResult,
+        Seek,
+        SeekFrom
     },
-    result::Result as StdResult
+    process::{Command, Stdio},
+    result::Result as StdResult,
+    time::Duration
 };
+use super::audio::{duration_to_frames, frames_to_duration, SAMPLE_RATE};
+use super::metadata::AudioMetadata;
+use super::ogg::OggOpusReader;
+use super::process::ProcessInput;
+use super::wav::WavReader;
 use super::{
     AudioSource, 
     AudioType, 
     DcaError, 
     DcaMetadata, 
-    VoiceError
+    OggError,
+    VoiceError,
+    WavError
 };
 
-struct ChildContainer(Child);
-
-impl Read for ChildContainer {
-    fn read(&mut self, buffer: &mut [u8]) -> IoResult<usize> {
-        self.0.stdout.as_mut().unwrap().read(buffer)
-    }
+pub(crate) struct InputSource<R: Read + Send + 'static> {
+    pub(crate) stereo: bool,
+    pub(crate) reader: R,
+    pub(crate) kind: AudioType,
 }
 
-impl Drop for ChildContainer {
-    fn drop (&mut self) {
-        if let Err(e) = self.0.kill() {
-            debug!("[Voice] Error awaiting child process: {:?}", e);
+impl<R: Read + Send> InputSource<R> {
+    pub(crate) fn new(stereo: bool, reader: R, kind: AudioType) -> Self {
+        Self {
+            stereo,
+            reader,
+            kind,
         }
     }
 }
 
-struct InputSource<R: Read + Send + 'static> {
-    stereo: bool,
-    reader: R,
-    kind: AudioType,
-}
-
 impl<R: Read + Send> AudioSource for InputSource<R> {
     fn is_stereo(&mut self) -> bool { self.stereo }
 
@@ -98,36 +103,405 @@ impl<R: Read + Send> AudioSource for InputSource<R> {
     }
 }
 
-/// Opens an audio file through `ffmpeg` and creates an audio source.
-pub fn ffmpeg<P: AsRef<OsStr>>(path: P) -> Result<Box<AudioSource>> {
-    let path = path.as_ref();
+/// An audio source backed by a seekable reader, such as a file.
+///
+/// PCM sources are seeked directly by byte offset, while Opus sources are
+/// seeked by walking over the length prefix of each frame.
+struct SeekableSource<R: Read + Seek + Send + 'static> {
+    inner: InputSource<R>,
+    metadata: AudioMetadata,
+    /// Offset of the first frame within the reader.
+    start: u64,
+    /// Number of frames read since the start of the stream.
+    frame: u64,
+}
 
-    // Will fail if the path is not to a file on the fs. Likely a YouTube URI.
-    let is_stereo = is_stereo(path).unwrap_or(false);
-    let stereo_val = if is_stereo { "2" } else { "1" };
+impl<R: Read + Seek + Send> SeekableSource<R> {
+    fn new(stereo: bool, mut reader: R, kind: AudioType) -> IoResult<Self> {
+        let start = reader.seek(SeekFrom::Current(0))?;
 
-    let args = [
-        "-f",
-        "s16le",
-        "-ac",
-        stereo_val,
-        "-ar",
-        "48000",
-        "-acodec",
-        "pcm_s16le",
-        "-",
-    ];
+        Ok(Self {
+            inner: InputSource::new(stereo, reader, kind),
+            metadata: AudioMetadata::default(),
+            start,
+            frame: 0,
+        })
+    }
 
-    let command = Command::new("ffmpeg")
-        .arg("-i")
-        .arg(path)
-        .args(&args)
-        .stderr(Stdio::null())
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .spawn()?;
+    fn pcm_frame_bytes(&self) -> u64 {
+        let channels = if self.inner.stereo { 2 } else { 1 };
+
+        960 * channels * 2
+    }
+
+    /// Skips over up to `count` Opus frames, stopping early at the end of
+    /// the stream.
+    fn skip_opus_frames(&mut self, count: u64) -> IoResult<()> {
+        for _ in 0..count {
+            let size = match self.inner.reader.read_i16::<LittleEndian>() {
+                Ok(size) => size,
+                Err(ref e) if e.kind() == IoErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            };
+
+            self.inner.reader.seek(SeekFrom::Current(i64::from(size)))?;
+            self.frame += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek + Send> AudioSource for SeekableSource<R> {
+    fn is_stereo(&mut self) -> bool { self.inner.stereo }
+
+    fn get_type(&self) -> AudioType { self.inner.kind }
+
+    fn read_pcm_frame(&mut self, buffer: &mut [i16]) -> Option<usize> {
+        let out = self.inner.read_pcm_frame(buffer);
+
+        if let Some(len) = out {
+            if len > 0 {
+                self.frame += 1;
+            }
+        }
+
+        out
+    }
+
+    fn read_opus_frame(&mut self) -> Option<Vec<u8>> {
+        let out = self.inner.read_opus_frame();
+
+        if let Some(ref frame) = out {
+            if !frame.is_empty() {
+                self.frame += 1;
+            }
+        }
+
+        out
+    }
+
+    fn is_seekable(&self) -> bool { true }
+
+    fn seek_time(&mut self, time: Duration) -> Option<Duration> {
+        let target = duration_to_frames(time);
+
+        match self.inner.kind {
+            AudioType::Pcm => {
+                let offset = self.start + target * self.pcm_frame_bytes();
+
+                self.inner.reader.seek(SeekFrom::Start(offset)).ok()?;
+                self.frame = target;
+            },
+            AudioType::Opus => {
+                // Frames are of variable length, so going backwards means
+                // walking forward again from the very first frame.
+                if target < self.frame {
+                    self.inner.reader.seek(SeekFrom::Start(self.start)).ok()?;
+                    self.frame = 0;
+                }
+
+                let remaining = target - self.frame;
+
+                self.skip_opus_frames(remaining).ok()?;
+            },
+        }
+
+        Some(frames_to_duration(self.frame))
+    }
+
+    fn metadata(&self) -> AudioMetadata { self.metadata.clone() }
+}
+
+/// An audio source which reads the packets of an Ogg Opus stream.
+///
+/// Seeking walks over packets from the first page of audio, as with DCA.
+///
+/// When decoded for mixing, the stream's pre-skip is discarded from its start,
+/// so later frames are made up of the end of one packet and the start of the
+/// next. Packets passed through to Discord untouched still carry the
+/// pre-skip, playing a few milliseconds of encoder priming.
+struct OggSource<R: Read + Seek + Send + 'static> {
+    reader: OggOpusReader<R>,
+    /// Offset of the first page of audio within the reader.
+    start: u64,
+    /// Number of frames read since the start of the stream.
+    frame: u64,
+    /// Decoded stereo samples left over from the last packet.
+    pending: Vec<f32>,
+    /// Samples per channel still to be discarded from the start of the stream.
+    skip: usize,
+}
+
+impl<R: Read + Seek + Send> OggSource<R> {
+    fn new(reader: R) -> StdResult<Self, OggError> {
+        let mut reader = OggOpusReader::new(reader)?;
+        let start = reader
+            .get_mut()
+            .seek(SeekFrom::Current(0))
+            .map_err(OggError::IoError)?;
+
+        let mut source = Self {
+            reader,
+            start,
+            frame: 0,
+            pending: Vec::new(),
+            skip: 0,
+        };
+
+        // Only 20ms frames can be passed on to Discord untouched.
+        if let Some(packet) = source.reader.next_packet().map_err(OggError::from_io)? {
+            let samples = opus_packet::get_nb_samples(&packet, SAMPLE_RATE).unwrap_or(0);
+
+            if samples != 960 {
+                return Err(OggError::UnsupportedFrameSize(samples));
+            }
+        }
+
+        source.rewind().map_err(OggError::IoError)?;
+
+        Ok(source)
+    }
+
+    fn rewind(&mut self) -> IoResult<()> {
+        self.reader.get_mut().seek(SeekFrom::Start(self.start))?;
+        self.reader.clear();
+        self.frame = 0;
+        self.pending.clear();
+        self.skip = self.reader.pre_skip();
+
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek + Send> AudioSource for OggSource<R> {
+    fn is_stereo(&mut self) -> bool { self.reader.is_stereo() }
+
+    fn get_type(&self) -> AudioType { AudioType::Opus }
+
+    fn read_pcm_frame(&mut self, _buffer: &mut [i16]) -> Option<usize> { None }
+
+    fn read_opus_frame(&mut self) -> Option<Vec<u8>> {
+        match self.reader.next_packet() {
+            Ok(Some(packet)) => {
+                self.frame += 1;
+
+                Some(packet)
+            },
+            Ok(None) => Some(Vec::new()),
+            Err(why) => {
+                warn!("[Voice] Error reading Ogg stream: {:?}", why);
+
+                None
+            },
+        }
+    }
+
+    fn decode_and_add_opus_frame(&mut self,
+                                 decoder: &mut Option<OpusDecoder>,
+                                 float_buffer: &mut [f32; 1920],
+                                 volume: f32)
+                                 -> Option<usize> {
+        // Packets are decoded until a whole frame is left over once the
+        // pre-skip has been discarded, or until the stream ends.
+        while self.pending.len() < 1920 {
+            let packet = self.read_opus_frame()?;
+
+            if packet.is_empty() {
+                break;
+            }
+
+            let mut decoded = [0f32; 1920];
+            let len = decode_and_add(lazy_decoder(decoder)?, &packet, &mut decoded, 1.0)?;
+            let skip = self.skip.min(len);
+
+            self.skip -= skip;
+            self.pending.extend_from_slice(&decoded[skip * 2..len * 2]);
+        }
+
+        let len = self.pending.len().min(1920);
+
+        for (mixed, sample) in float_buffer.iter_mut().zip(self.pending.drain(..len)) {
+            *mixed += sample * volume;
+        }
+
+        Some(len / 2)
+    }
+
+    fn is_seekable(&self) -> bool { true }
+
+    fn seek_time(&mut self, time: Duration) -> Option<Duration> {
+        let target = duration_to_frames(time);
+
+        if target < self.frame {
+            self.rewind().ok()?;
+        }
+
+        while self.frame < target {
+            match self.reader.next_packet().ok()? {
+                Some(_) => self.frame += 1,
+                None => break,
+            }
+        }
+
+        // Only the very start of the stream holds the pre-skip.
+        self.pending.clear();
+
+        if self.frame != 0 {
+            self.skip = 0;
+        }
 
-    Ok(pcm(is_stereo, ChildContainer(command)))
+        Some(frames_to_duration(self.frame))
+    }
+}
+
+/// An audio source which converts the samples of a WAV file to 16-bit PCM at
+/// 48kHz.
+///
+/// Other sample rates are converted by linear interpolation between the
+/// file's frames.
+struct WavSource<R: Read + Seek + Send + 'static> {
+    /// Fractional position between `prev` and `next`, in input frames.
+    fraction: f64,
+    next: Option<[f32; 2]>,
+    prev: Option<[f32; 2]>,
+    reader: WavReader<R>,
+    /// Input frames advanced per output frame.
+    step: f64,
+}
+
+impl<R: Read + Seek + Send> WavSource<R> {
+    fn new(reader: R) -> StdResult<Self, WavError> {
+        let reader = WavReader::new(reader)?;
+        let step = f64::from(reader.sample_rate()) / f64::from(SAMPLE_RATE);
+
+        let mut source = Self {
+            fraction: 0.0,
+            next: None,
+            prev: None,
+            reader,
+            step,
+        };
+
+        source.prime().map_err(WavError::IoError)?;
+
+        Ok(source)
+    }
+
+    /// Loads the first two frames from the reader's current position.
+    fn prime(&mut self) -> IoResult<()> {
+        self.fraction = 0.0;
+        self.prev = self.reader.read_frame()?;
+        self.next = self.reader.read_frame()?;
+
+        Ok(())
+    }
+
+    /// Produces the next output frame, or `None` at the end of the file.
+    fn next_frame(&mut self) -> IoResult<Option<[f32; 2]>> {
+        let prev = match self.prev {
+            Some(prev) => prev,
+            None => return Ok(None),
+        };
+
+        let next = self.next.unwrap_or(prev);
+        let t = self.fraction as f32;
+        let frame = [
+            prev[0] + (next[0] - prev[0]) * t,
+            prev[1] + (next[1] - prev[1]) * t,
+        ];
+
+        self.fraction += self.step;
+
+        while self.fraction >= 1.0 {
+            self.fraction -= 1.0;
+            self.prev = self.next;
+
+            if self.prev.is_none() {
+                break;
+            }
+
+            self.next = self.reader.read_frame()?;
+        }
+
+        Ok(Some(frame))
+    }
+}
+
+impl<R: Read + Seek + Send> AudioSource for WavSource<R> {
+    fn is_stereo(&mut self) -> bool { self.reader.is_stereo() }
+
+    fn get_type(&self) -> AudioType { AudioType::Pcm }
+
+    fn read_pcm_frame(&mut self, buffer: &mut [i16]) -> Option<usize> {
+        let channels = if self.reader.is_stereo() { 2 } else { 1 };
+        let mut len = 0;
+
+        for out in buffer.chunks_mut(channels) {
+            let frame = match self.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(why) => {
+                    warn!("[Voice] Error reading WAV file: {:?}", why);
+
+                    return None;
+                },
+            };
+
+            for (sample, value) in out.iter_mut().zip(frame.iter()) {
+                *sample = (value * 32_768.0).max(-32_768.0).min(32_767.0) as i16;
+            }
+
+            len += out.len();
+        }
+
+        Some(len)
+    }
+
+    fn read_opus_frame(&mut self) -> Option<Vec<u8>> { None }
+
+    fn is_seekable(&self) -> bool { true }
+
+    fn seek_time(&mut self, time: Duration) -> Option<Duration> {
+        let frames = duration_to_frames(time);
+        let output_samples = frames * 960;
+        let input_samples = output_samples * u64::from(self.reader.sample_rate())
+            / u64::from(SAMPLE_RATE);
+
+        self.reader.seek_frame(input_samples).ok()?;
+        self.prime().ok()?;
+
+        Some(frames_to_duration(frames))
+    }
+
+    fn metadata(&self) -> AudioMetadata {
+        let sample_rate = self.reader.sample_rate();
+        let millis = self.reader.frame_count() * 1000 / u64::from(sample_rate.max(1));
+
+        AudioMetadata {
+            duration: Some(Duration::from_millis(millis)),
+            sample_rate: Some(sample_rate),
+            channels: Some(if self.reader.is_stereo() { 2 } else { 1 }),
+            ..Default::default()
+        }
+    }
+}
+
+/// Opens an audio file through `ffmpeg` and creates an audio source.
+///
+/// The resulting source can be seeked, which restarts `ffmpeg` at the
+/// requested position.
+///
+/// The input is first inspected with `ffprobe`, and the title, artist,
+/// duration, sample rate and channel count it reports are made available
+/// through the source's [`metadata`].
+///
+/// See [`ProcessInput`] to pass further arguments to `ffmpeg`.
+///
+/// [`ProcessInput`]: struct.ProcessInput.html
+/// [`metadata`]: trait.AudioSource.html#method.metadata
+pub fn ffmpeg<P: AsRef<OsStr>>(path: P) -> Result<Box<AudioSource>> {
+    ProcessInput::new(path).build()
 }
 
 /// Creates a streamed audio source from a DCA file.
@@ -168,33 +542,75 @@ pub fn dca<P: AsRef<OsStr>>(path: P) -> StdResult<Box<AudioSource>, DcaError> {
     let metadata = serde_json::from_slice::<DcaMetadata>(raw_json.as_slice())
         .map_err(DcaError::InvalidMetadata)?;
 
-    Ok(opus(metadata.is_stereo(), reader))
+    let mut source = SeekableSource::new(metadata.is_stereo(), reader, AudioType::Opus)
+        .map_err(DcaError::IoError)?;
+
+    source.metadata = metadata.audio_metadata();
+
+    Ok(Box::new(source))
+}
+
+/// Creates a streamed audio source from an Ogg Opus file, such as a `.opus`
+/// file, without transcoding.
+///
+/// The stream must be made up of 20ms frames, as produced by most encoders by
+/// default. Other files can be played through [`ffmpeg`].
+///
+/// The encoder's pre-skip is trimmed from the start of the track when it is
+/// decoded for mixing, but not when its packets are passed straight through,
+/// in which case the first frame carries a few milliseconds of priming.
+///
+/// [`ffmpeg`]: fn.ffmpeg.html
+pub fn ogg<P: AsRef<OsStr>>(path: P) -> StdResult<Box<AudioSource>, OggError> {
+    let file = File::open(path.as_ref()).map_err(OggError::IoError)?;
+
+    OggSource::new(BufReader::new(file)).map(|source| Box::new(source) as Box<AudioSource>)
 }
 
 /// Creates an Opus audio source. This makes certain assumptions: namely, that the input stream
 /// is composed ONLY of opus frames of the variety that Discord expects.
 ///
-/// If you want to decode a `.opus` file, use [`ffmpeg`]
+/// If you want to play a `.opus` file, use [`ogg`] or [`ffmpeg`].
 ///
 /// [`ffmpeg`]: fn.ffmpeg.html
+/// [`ogg`]: fn.ogg.html
 pub fn opus<R: Read + Send + 'static>(is_stereo: bool, reader: R) -> Box<AudioSource> {
-    Box::new(InputSource {
-        stereo: is_stereo,
-        reader,
-        kind: AudioType::Opus,
-    })
+    Box::new(InputSource::new(is_stereo, reader, AudioType::Opus))
 }
 
 /// Creates a PCM audio source.
 pub fn pcm<R: Read + Send + 'static>(is_stereo: bool, reader: R) -> Box<AudioSource> {
-    Box::new(InputSource {
-        stereo: is_stereo,
-        reader,
-        kind: AudioType::Pcm,
-    })
+    Box::new(InputSource::new(is_stereo, reader, AudioType::Pcm))
+}
+
+/// Creates a seekable PCM audio source, such as one backed by a file.
+///
+/// The current position of the reader is treated as the start of the stream.
+pub fn pcm_seekable<R>(is_stereo: bool, reader: R) -> Result<Box<AudioSource>>
+    where R: Read + Seek + Send + 'static {
+    let source = SeekableSource::new(is_stereo, reader, AudioType::Pcm)?;
+
+    Ok(Box::new(source))
+}
+
+/// Creates a streamed audio source from a WAV file, without the need for
+/// `ffmpeg`.
+///
+/// Files may hold mono or stereo audio, stored as 8, 16, 24 or 32-bit integer
+/// or 32-bit floating point samples. Sample rates other than 48kHz are
+/// resampled as the file is played.
+pub fn wav<P: AsRef<OsStr>>(path: P) -> StdResult<Box<AudioSource>, WavError> {
+    let file = File::open(path.as_ref()).map_err(WavError::IoError)?;
+
+    WavSource::new(BufReader::new(file)).map(|source| Box::new(source) as Box<AudioSource>)
 }
 
 /// Creates a streamed audio source with `youtube-dl` and `ffmpeg`.
+///
+/// The title, uploader, duration, thumbnail and page URL reported by
+/// `youtube-dl` are made available through the source's [`metadata`].
+///
+/// [`metadata`]: trait.AudioSource.html#method.metadata
 pub fn ytdl(uri: &str) -> Result<Box<AudioSource>> {
     let args = [
         "-f",
@@ -220,6 +636,8 @@ pub fn ytdl(uri: &str) -> Result<Box<AudioSource>> {
         other => return Err(Error::Voice(VoiceError::YouTubeDLProcessing(other))),
     };
 
+    let metadata = AudioMetadata::from_ytdl(&obj);
+
     let uri = match obj.remove("url") {
         Some(v) => match v {
             Value::String(uri) => uri,
@@ -228,11 +646,50 @@ pub fn ytdl(uri: &str) -> Result<Box<AudioSource>> {
         None => return Err(Error::Voice(VoiceError::YouTubeDLUrl(Value::Object(obj)))),
     };
 
-    ffmpeg(&uri)
+    ProcessInput::new(&uri).with_metadata(metadata).build()
+}
+
+/// Decodes a single Opus frame to stereo, adding the result into
+/// `float_buffer` scaled by `volume`.
+///
+/// Returns the number of samples decoded per channel. A frame which can't be
+/// decoded is skipped, leaving a frame of silence, so that one corrupt frame
+/// doesn't end the whole track.
+pub(crate) fn decode_and_add(decoder: &mut OpusDecoder,
+                             frame: &[u8],
+                             float_buffer: &mut [f32; 1920],
+                             volume: f32)
+                             -> Option<usize> {
+    let mut decoded = [0f32; 1920];
+
+    let len = match decoder.decode_float(frame, &mut decoded[..], false) {
+        Ok(len) => len,
+        Err(why) => {
+            warn!("[Voice] Skipping undecodable opus frame: {:?}", why);
+
+            return Some(960);
+        },
+    };
+
+    for (mixed, sample) in float_buffer.iter_mut().zip(decoded[..len * 2].iter()) {
+        *mixed += sample * volume;
+    }
+
+    Some(len)
+}
+
+/// The decoder kept for a track, which is only created once the track first
+/// needs decoding.
+pub(crate) fn lazy_decoder(decoder: &mut Option<OpusDecoder>) -> Option<&mut OpusDecoder> {
+    if decoder.is_none() {
+        *decoder = Some(OpusDecoder::new(SAMPLE_RATE, Channels::Stereo).ok()?);
+    }
+
+    decoder.as_mut()
 }
 
-fn is_stereo(path: &OsStr) -> Result<bool> {
-    let args = ["-v", "quiet", "-of", "json", "-show-streams", "-i"];
+pub(crate) fn probe(path: &OsStr) -> Result<AudioMetadata> {
+    let args = ["-v", "quiet", "-of", "json", "-show_streams", "-show_format", "-i"];
 
     let out = Command::new("ffprobe")
         .args(&args)
@@ -242,19 +699,59 @@ fn is_stereo(path: &OsStr) -> Result<bool> {
 
     let value: Value = serde_json::from_reader(&out.stdout[..])?;
 
-    let streams = value
-        .as_object()
-        .and_then(|m| m.get("streams"))
-        .and_then(|v| v.as_array())
-        .ok_or(Error::Voice(VoiceError::Streams))?;
+    if value.get("streams").and_then(Value::as_array).is_none() {
+        return Err(Error::Voice(VoiceError::Streams));
+    }
+
+    Ok(AudioMetadata::from_ffprobe(&value))
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+    use std::io::Cursor;
+    use super::*;
+
+    /// A mono 16-bit WAV file holding the given samples.
+    fn wav_file(sample_rate: u32, samples: &[i16]) -> Vec<u8> {
+        let mut file = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        file.write_u32::<LittleEndian>(16).unwrap();
+        file.write_u16::<LittleEndian>(1).unwrap();
+        file.write_u16::<LittleEndian>(1).unwrap();
+        file.write_u32::<LittleEndian>(sample_rate).unwrap();
+        file.write_u32::<LittleEndian>(sample_rate * 2).unwrap();
+        file.write_u16::<LittleEndian>(2).unwrap();
+        file.write_u16::<LittleEndian>(16).unwrap();
+        file.extend_from_slice(b"data");
+        file.write_u32::<LittleEndian>(samples.len() as u32 * 2).unwrap();
+
+        for &sample in samples {
+            file.write_i16::<LittleEndian>(sample).unwrap();
+        }
+
+        file
+    }
+
+    #[test]
+    fn wav_is_resampled_by_interpolation() {
+        let file = wav_file(24_000, &[0, 16_384, 0]);
+        let mut source = WavSource::new(Cursor::new(file)).unwrap();
+        let mut buffer = [0i16; 960];
+
+        let len = source.read_pcm_frame(&mut buffer).unwrap();
 
-    let check = streams.iter().any(|stream| {
-        let channels = stream
-            .as_object()
-            .and_then(|m| m.get("channels").and_then(|v| v.as_i64()));
+        assert_eq!(&buffer[..len], &[0, 8_192, 16_384, 8_192, 0, 0]);
+        assert_eq!(source.read_pcm_frame(&mut buffer), Some(0));
+    }
+
+    #[test]
+    fn wav_at_output_rate_is_unchanged() {
+        let samples = [100, -200, 300, -400];
+        let mut source = WavSource::new(Cursor::new(wav_file(SAMPLE_RATE, &samples))).unwrap();
+        let mut buffer = [0i16; 960];
 
-        channels == Some(2)
-    });
+        let len = source.read_pcm_frame(&mut buffer).unwrap();
 
-    Ok(check)
+        assert_eq!(&buffer[..len], &samples);
+    }
 }
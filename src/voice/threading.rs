@@ -1,103 +1,555 @@
-use internal::Timer;
-use model::id::GuildId;
+use constants::voice_close_codes;
+use gateway::GatewayError;
+use internal::prelude::*;
+use internal::{Tick, Timer};
+use model::{
+    id::GuildId,
+    voice::SpeakingState
+};
+use parking_lot::Mutex;
 use std::{
-    sync::mpsc::{Receiver as MpscReceiver, TryRecvError},
-    thread::Builder as ThreadBuilder
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver as MpscReceiver, TryRecvError},
+        Arc
+    },
+    thread::{self, Builder as ThreadBuilder},
+    time::{Duration, Instant}
 };
+use threadpool::ThreadPool;
 use super::{
     connection::Connection,
-    Status
+    connection_info::ConnectionInfo,
+    ducking::Ducker,
+    events,
+    state::SharedState,
+    AudioReceiver,
+    ConnectionState,
+    EncoderSettings,
+    LockedAudio,
+    MixerPool,
+    Status,
+    TrackQueue,
+    VoiceError,
+    VoiceStats
 };
 
-pub(crate) fn start(guild_id: GuildId, rx: MpscReceiver<Status>) {
+/// Number of times to attempt a full reconnection before giving up.
+const RECONNECT_ATTEMPTS: u32 = 5;
+
+/// Delay after the first failed reconnection attempt, doubled after each
+/// subsequent failure.
+const RECONNECT_BASE_DELAY_MS: u64 = 500;
+
+pub(crate) fn start(guild_id: GuildId,
+                    rx: MpscReceiver<Status>,
+                    queue: TrackQueue,
+                    stats: Arc<Mutex<VoiceStats>>,
+                    state: SharedState,
+                    pool: Option<&MixerPool>) {
+    let mut runner = Runner::new(rx, queue, stats, state, pool.map(MixerPool::connector));
+
+    if let Some(pool) = pool {
+        runner = match pool.add(runner) {
+            Ok(()) => return,
+            Err(mut runner) => {
+                warn!("[Voice] Mixer pool has shut down; starting a thread for guild {}", guild_id);
+
+                runner.connector = None;
+
+                runner
+            },
+        };
+    }
+
     let name = format!("Serenity Voice (G{})", guild_id);
 
     ThreadBuilder::new()
         .name(name)
-        .spawn(move || runner(&rx))
+        .spawn(move || runner.run())
         .expect(&format!("[Voice] Error starting guild: {:?}", guild_id));
 }
 
-fn runner(rx: &MpscReceiver<Status>) {
-    let mut senders = Vec::new();
-    let mut receiver = None;
-    let mut connection = None;
-    let mut timer = Timer::new(20);
+/// The state of a single guild's voice connection, driven either by its own
+/// thread or by a [`MixerPool`].
+///
+/// [`MixerPool`]: struct.MixerPool.html
+pub(crate) struct Runner {
+    connection: Option<Connection>,
+    /// The threads of the runner's [`MixerPool`] which establish connections,
+    /// if the runner shares its thread with others and so must not block.
+    ///
+    /// [`MixerPool`]: struct.MixerPool.html
+    connector: Option<ThreadPool>,
+    ducking: Option<Ducker>,
+    encoder_settings: EncoderSettings,
+    /// The voice server of the last established connection, kept while
+    /// disconnected so that moving to a new server can be noticed.
+    endpoint: Option<String>,
+    /// A connection being established in the background.
+    pending: Option<PendingConnection>,
+    queue: TrackQueue,
+    receiver: Option<Box<AudioReceiver>>,
+    /// A connection being recovered by reconnecting from scratch.
+    retry: Option<Retry>,
+    rx: MpscReceiver<Status>,
+    senders: Vec<LockedAudio>,
+    speaking: SpeakingState,
+    state: SharedState,
+    stats: Arc<Mutex<VoiceStats>>,
+}
+
+impl Runner {
+    fn new(rx: MpscReceiver<Status>,
+           queue: TrackQueue,
+           stats: Arc<Mutex<VoiceStats>>,
+           state: SharedState,
+           connector: Option<ThreadPool>)
+           -> Self {
+        Self {
+            connection: None,
+            connector,
+            ducking: None,
+            encoder_settings: EncoderSettings::default(),
+            endpoint: None,
+            pending: None,
+            queue,
+            receiver: None,
+            retry: None,
+            rx,
+            senders: Vec::new(),
+            speaking: SpeakingState::MICROPHONE,
+            state,
+            stats,
+        }
+    }
 
-    'runner: loop {
+    /// Drives the runner on its own thread until its handler is dropped.
+    fn run(mut self) {
+        let mut timer = Timer::new(20);
+
+        while self.receive() {
+            let mixed = self.mix();
+            let tick = timer.await();
+
+            self.send(mixed, tick);
+        }
+    }
+
+    /// Shuts down a pooled runner whose code has panicked, leaving its handler
+    /// to start a new one the next time it sends a message.
+    pub(crate) fn abandon(mut self) {
+        error!("[Voice] Dropping a connection which panicked on its mixer thread");
+
+        self.pending = None;
+        self.retry = None;
+        self.set_connection(None);
+    }
+
+    /// Handles every message sent by the handler since the last frame.
+    ///
+    /// Returns `false` once the handler has been dropped.
+    pub(crate) fn receive(&mut self) -> bool {
         loop {
-            match rx.try_recv() {
+            match self.rx.try_recv() {
                 Ok(Status::Connect(info)) => {
-                    connection = match Connection::new(info) {
+                    let polled = self.connector.is_some();
+                    let state = self.state.clone();
+
+                    self.retry = None;
+                    self.state.set(ConnectionState::Connecting);
+
+                    self.establish(move |cancelled| match Connection::new(info, polled) {
                         Ok(connection) => Some(connection),
                         Err(why) => {
                             warn!("[Voice] Error connecting: {:?}", why);
 
+                            if !cancelled.load(Ordering::Relaxed) {
+                                state.set(ConnectionState::Failed(connect_error(why)));
+                            }
+
                             None
                         },
-                    };
+                    });
                 },
                 Ok(Status::Disconnect) => {
-                    connection = None;
+                    self.pending = None;
+                    self.retry = None;
+                    self.set_connection(None);
                 },
                 Ok(Status::SetReceiver(r)) => {
-                    receiver = r;
+                    self.receiver = r;
                 },
                 Ok(Status::SetSender(s)) => {
-                    senders.clear();
+                    events::stop_all(&mut self.senders);
 
                     if let Some(aud) = s {
-                        senders.push(aud);
+                        self.senders.push(aud);
                     }
                 },
                 Ok(Status::AddSender(s)) => {
-                    senders.push(s);
+                    self.senders.push(s);
+                },
+                Ok(Status::SetEncoderSettings(settings)) => {
+                    self.encoder_settings = settings;
+
+                    if let Some(ref mut connection) = self.connection {
+                        apply_encoder_settings(connection, settings);
+                    }
+                },
+                Ok(Status::SetDucking(settings)) => {
+                    match (self.ducking.as_mut(), settings) {
+                        (Some(ducker), Some(settings)) => ducker.set_settings(settings),
+                        (_, settings) => self.ducking = settings.map(Ducker::new),
+                    }
+                },
+                Ok(Status::SetSpeaking(speaking)) => {
+                    self.speaking = speaking;
+
+                    if let Some(ref mut connection) = self.connection {
+                        apply_speaking_state(connection, speaking);
+                    }
                 },
                 Err(TryRecvError::Empty) => {
                     // If we receieved nothing, then we can perform an update.
-                    break;
+                    return true;
                 },
                 Err(TryRecvError::Disconnected) => {
-                    break 'runner;
+                    return false;
                 },
             }
         }
+    }
+
+    /// Mixes the next frame of audio, without sending it.
+    pub(crate) fn mix(&mut self) -> Result<Option<usize>> {
+        match self.poll_pending() {
+            Some(Some(connection)) => {
+                self.retry = None;
+                self.set_connection(Some(connection));
+            },
+            Some(None) => self.retry_later(),
+            None => {},
+        }
+
+        self.poll_retry();
+
+        match self.connection {
+            Some(ref mut connection) => {
+                connection.mix(&self.senders, &mut self.receiver, self.ducking.as_mut())
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Sends a frame mixed by [`mix`] on the given tick of the frame timer,
+    /// then runs any track callbacks and advances the queue.
+    ///
+    /// [`mix`]: #method.mix
+    pub(crate) fn send(&mut self, mixed: Result<Option<usize>>, tick: Tick) {
+        if let Some(ref mut connection) = self.connection {
+            connection.record_tick(tick);
+        }
 
         // Overall here, check if there's an error.
         //
         // If there is a connection, try to send an update. This should not
-        // error. If there is though for some spurious reason, then set `error`
-        // to `true`.
-        //
-        // Otherwise, wait out the timer and do _not_ error and wait to receive
-        // another event.
-        let error = match connection.as_mut() {
-            Some(connection) => {
-                let cycle = connection.cycle(&mut senders, &mut receiver, &mut timer);
-
-                match cycle {
-                    Ok(()) => false,
-                    Err(why) => {
-                        error!(
-                            "(╯°□°）╯︵ ┻━┻ Error updating connection: {:?}",
-                            why
-                        );
-
-                        true
-                    },
+        // error. If there is though for some spurious reason, such as the
+        // connection dropping, then keep hold of the error.
+        let error = match (mixed, self.connection.as_mut()) {
+            (Ok(Some(len)), Some(connection)) => connection.send_mixed(len).err(),
+            (Ok(_), _) => None,
+            (Err(why), _) => Some(why),
+        };
+
+        if let Some(ref connection) = self.connection {
+            self.stats.lock().clone_from(connection.stats());
+        }
+
+        // Run any track callbacks due after this frame, and drop finished
+        // tracks.
+        events::process(&mut self.senders);
+
+        // Start the next queued track, if the last one has finished.
+        self.queue.advance(&mut self.senders);
+
+        // If there was an error, then try to bring the connection back. Tracks
+        // and the receiver are kept as-is, so playback carries on afterwards.
+        let why = match error {
+            Some(why) => why,
+            None => return,
+        };
+
+        error!("(╯°□°）╯︵ ┻━┻ Error updating connection: {:?}", why);
+
+        let close_code = match why {
+            Error::Gateway(GatewayError::Closed(Some(ref data))) => Some(data.status_code),
+            _ => None,
+        };
+
+        let connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        // A connection is already on its way, such as to a new server which
+        // Discord has moved the guild to, so the old one isn't worth saving.
+        if self.pending.is_some() {
+            *self.stats.lock() = VoiceStats::default();
+
+            return;
+        }
+
+        self.state.set(ConnectionState::Reconnecting);
+        self.reconnect(connection, close_code);
+    }
+
+    /// Recovers a connection after an error, by resuming its session if
+    /// possible and otherwise reconnecting from scratch with backoff.
+    ///
+    /// Attempts are started from the runner's ticks once due, rather than
+    /// sleeping between them, so that a connector thread is only held for as
+    /// long as a single handshake.
+    fn reconnect(&mut self, mut connection: Connection, close_code: Option<u16>) {
+        let resumable = match close_code {
+            Some(voice_close_codes::NOT_AUTHENTICATED) |
+            Some(voice_close_codes::AUTHENTICATION_FAILED) |
+            Some(voice_close_codes::SERVER_NOT_FOUND) |
+            Some(voice_close_codes::UNKNOWN_PROTOCOL) |
+            Some(voice_close_codes::DISCONNECTED) |
+            Some(voice_close_codes::UNKNOWN_ENCRYPTION_MODE) => {
+                info!("[Voice] Connection closed with code {:?}; not reconnecting", close_code);
+
+                self.set_connection(None);
+
+                return;
+            },
+            // The session is gone, so there's nothing to resume.
+            Some(voice_close_codes::SESSION_NO_LONGER_VALID) |
+            Some(voice_close_codes::SESSION_TIMEOUT) => false,
+            _ => true,
+        };
+
+        self.retry = Some(Retry {
+            attempts: 0,
+            due: None,
+            info: connection.info().clone(),
+            polled: connection.is_polled(),
+        });
+
+        if resumable {
+            self.establish(move |_| match connection.resume() {
+                Ok(()) => Some(connection),
+                Err(why) => {
+                    warn!("[Voice] Error resuming connection: {:?}", why);
+
+                    None
+                },
+            });
+        } else {
+            // Shut down the old connection's threads before replacing it.
+            drop(connection);
+
+            self.retry_later();
+        }
+    }
+
+    /// Schedules the next reconnection attempt after the last has failed, or
+    /// gives up on the connection once they have run out.
+    fn retry_later(&mut self) {
+        let gave_up = match self.retry {
+            Some(ref mut retry) if retry.attempts < RECONNECT_ATTEMPTS => {
+                // The first attempt follows straight on from a failed resume.
+                let delay = match retry.attempts {
+                    0 => 0,
+                    attempts => RECONNECT_BASE_DELAY_MS << (attempts - 1),
+                };
+
+                retry.due = Some(Instant::now() + Duration::from_millis(delay));
+
+                return;
+            },
+            Some(ref retry) => Some(retry.info.endpoint.clone()),
+            None => None,
+        };
+
+        if let Some(endpoint) = gave_up {
+            error!("[Voice] Giving up on reconnecting to: {}", endpoint);
+        }
+
+        self.retry = None;
+        self.set_connection(None);
+    }
+
+    /// Starts the next reconnection attempt, if it is due.
+    fn poll_retry(&mut self) {
+        if self.pending.is_some() {
+            return;
+        }
+
+        let retry = match self.retry {
+            Some(ref mut retry) => retry,
+            None => return,
+        };
+
+        match retry.due {
+            Some(due) if due <= Instant::now() => retry.due = None,
+            _ => return,
+        }
+
+        retry.attempts += 1;
+
+        let (info, polled, attempt) = (retry.info.clone(), retry.polled, retry.attempts);
+
+        self.establish(move |_| match Connection::new(info, polled) {
+            Ok(connection) => {
+                info!("[Voice] Reconnected after {} attempt(s)", attempt);
+
+                Some(connection)
+            },
+            Err(why) => {
+                warn!("[Voice] Reconnection attempt {} failed: {:?}", attempt, why);
+
+                None
+            },
+        });
+    }
+
+    /// Replaces the connection with the result of `connect`, which is run in
+    /// the background so that the runner keeps answering its handler.
+    ///
+    /// `connect` is run on the pool's connector threads if the runner is
+    /// pooled, and otherwise on a thread of its own. It is passed a flag which
+    /// is set if the attempt is abandoned, such as by a disconnect.
+    fn establish<F>(&mut self, connect: F)
+        where F: FnOnce(&AtomicBool) -> Option<Connection> + Send + 'static {
+        let (tx, rx) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&cancelled);
+
+        let job = move || {
+            let _ = tx.send(connect(&flag));
+        };
+
+        match self.connector {
+            Some(ref connector) => connector.execute(job),
+            None => {
+                let name = format!("{} Connector", thread::current().name().unwrap_or("Serenity Voice"));
+
+                if let Err(why) = ThreadBuilder::new().name(name).spawn(job) {
+                    warn!("[Voice] Error starting connection thread: {:?}", why);
+
+                    self.pending = None;
+                    self.retry = None;
+                    self.set_connection(None);
+
+                    return;
                 }
             },
+        }
+
+        self.pending = Some(PendingConnection {
+            cancelled,
+            rx,
+        });
+    }
+
+    /// Takes the result of a background connection attempt, if it has
+    /// finished.
+    fn poll_pending(&mut self) -> Option<Option<Connection>> {
+        let result = match self.pending {
+            Some(ref pending) => pending.rx.try_recv(),
+            None => return None,
+        };
+
+        match result {
+            Ok(connection) => {
+                self.pending = None;
+
+                Some(connection)
+            },
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.pending = None;
+
+                Some(None)
+            },
+        }
+    }
+
+    /// Replaces the connection, keeping all tracks and the receiver so that
+    /// playback carries on over the new one.
+    fn set_connection(&mut self, connection: Option<Connection>) {
+        self.connection = connection;
+
+        let connection = match self.connection {
+            Some(ref mut connection) => connection,
             None => {
-                timer.await();
+                *self.stats.lock() = VoiceStats::default();
+                self.state.set(ConnectionState::Disconnected);
 
-                false
+                return;
             },
         };
 
-        // If there was an error, then just reset the connection and try to get
-        // another.
-        if error {
-            connection = None;
+        apply_encoder_settings(connection, self.encoder_settings);
+        apply_speaking_state(connection, self.speaking);
+        self.state.set(ConnectionState::Connected);
+
+        let endpoint = connection.info().endpoint.clone();
+
+        if let Some(old) = self.endpoint.take() {
+            if old != endpoint {
+                info!("[Voice] Moved from {} to {}", old, endpoint);
+
+                if let Some(ref mut receiver) = self.receiver {
+                    receiver.region_changed(&old, &endpoint);
+                }
+            }
         }
+
+        self.endpoint = Some(endpoint);
+    }
+}
+
+/// The progress of reconnecting after a connection was lost.
+struct Retry {
+    /// Number of attempts made to reconnect from scratch.
+    attempts: u32,
+    /// When the next attempt should start, unless one is in progress.
+    due: Option<Instant>,
+    info: ConnectionInfo,
+    polled: bool,
+}
+
+/// A connection being established in the background, which is abandoned once
+/// this is dropped.
+struct PendingConnection {
+    cancelled: Arc<AtomicBool>,
+    rx: MpscReceiver<Option<Connection>>,
+}
+
+impl Drop for PendingConnection {
+    fn drop(&mut self) { self.cancelled.store(true, Ordering::Relaxed); }
+}
+
+/// Converts an error from establishing a connection into one which can be
+/// shared with anyone waiting on the connection.
+fn connect_error(why: Error) -> VoiceError {
+    match why {
+        Error::Voice(why) => why,
+        other => VoiceError::Connect(format!("{:?}", other)),
+    }
+}
+
+fn apply_encoder_settings(connection: &mut Connection, settings: EncoderSettings) {
+    if let Err(why) = connection.set_encoder_settings(settings) {
+        warn!("[Voice] Error applying encoder settings: {:?}", why);
+    }
+}
+
+fn apply_speaking_state(connection: &mut Connection, speaking: SpeakingState) {
+    if let Err(why) = connection.set_speaking_state(speaking) {
+        warn!("[Voice] Error applying speaking state: {:?}", why);
     }
 }
@@ -0,0 +1,544 @@
+use model::voice::SpeakingState;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant}
+};
+use super::audio::{duration_to_frames, FRAME_LEN_MS};
+use super::AudioReceiver;
+
+/// Consecutive loud frames needed before a user is considered to be talking,
+/// so that clicks and pops are ignored.
+const ATTACK_FRAMES: u32 = 2;
+
+type ActivityCallback = Box<FnMut(ActivityEvent) + Send>;
+
+/// A change in whether a user is talking, as detected by a [`VoiceActivity`]
+/// receiver.
+///
+/// [`VoiceActivity`]: struct.VoiceActivity.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ActivityEvent {
+    /// A user started talking.
+    Started {
+        ssrc: u32,
+        /// The user sending audio with the SSRC, if known.
+        user_id: Option<u64>,
+        /// The RTP timestamp of the first frame of speech.
+        timestamp: u32,
+        /// When the first frame of speech was received.
+        at: Instant,
+    },
+    /// A user stopped talking.
+    Stopped {
+        ssrc: u32,
+        /// The user sending audio with the SSRC, if known.
+        user_id: Option<u64>,
+        /// The RTP timestamp of the last frame of speech.
+        timestamp: u32,
+        /// When the last frame of speech was received.
+        at: Instant,
+        /// How long the user was talking for.
+        duration: Duration,
+    },
+}
+
+/// Speaking statistics for a single SSRC, gathered by a [`VoiceActivity`]
+/// receiver.
+///
+/// [`VoiceActivity`]: struct.VoiceActivity.html
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpeakerStats {
+    /// The user sending audio with the SSRC, if known.
+    pub user_id: Option<u64>,
+    /// Whether the user is currently talking.
+    pub talking: bool,
+    /// The total time the user has spent talking.
+    pub talk_time: Duration,
+    /// The loudest sample heard while the user was talking, as a fraction of
+    /// full scale.
+    pub peak: f32,
+    sum_squares: f64,
+    samples: u64,
+}
+
+impl SpeakerStats {
+    /// The average loudness of the user while talking, as an RMS fraction of
+    /// full scale.
+    pub fn rms(&self) -> f32 {
+        if self.samples == 0 {
+            0.0
+        } else {
+            (self.sum_squares / self.samples as f64).sqrt() as f32
+        }
+    }
+}
+
+/// A shared handle to the statistics gathered by a [`VoiceActivity`]
+/// receiver, which stays usable after the receiver is given to a [`Handler`].
+///
+/// [`Handler`]: struct.Handler.html
+/// [`VoiceActivity`]: struct.VoiceActivity.html
+#[derive(Clone, Debug, Default)]
+pub struct SpeakingAnalytics {
+    inner: Arc<Mutex<HashMap<u32, SpeakerStats>>>,
+}
+
+impl SpeakingAnalytics {
+    /// The statistics of the given SSRC, if any audio has been received from
+    /// it.
+    pub fn get(&self, ssrc: u32) -> Option<SpeakerStats> { self.inner.lock().get(&ssrc).cloned() }
+
+    /// The statistics of every SSRC audio has been received from.
+    pub fn snapshot(&self) -> HashMap<u32, SpeakerStats> { self.inner.lock().clone() }
+
+    /// Clears all gathered statistics.
+    pub fn reset(&self) { self.inner.lock().clear(); }
+}
+
+/// Voice activity detection state for a single SSRC.
+#[derive(Default)]
+struct Detector {
+    loud_frames: u32,
+    quiet_frames: u32,
+    talking: bool,
+    start: Option<(u32, Instant)>,
+    last_loud: Option<(u32, Instant)>,
+}
+
+/// An [`AudioReceiver`] which detects when each user starts and stops talking
+/// from the energy of their decoded audio, rather than relying on Discord's
+/// speaking flag.
+///
+/// Every call is also passed on to a wrapped receiver, if any, so that the
+/// detector can run alongside a [`Recorder`] or similar.
+///
+/// As clients stop sending audio once they fall silent, a user is also
+/// considered to have stopped talking once no audio has been received from
+/// them for the [`hangover`] period, or when Discord reports that they have
+/// stopped speaking.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use serenity::voice::{ActivityEvent, Recorder, RecordingFormat, VoiceActivity};
+///
+/// let recorder = Recorder::new("recordings", RecordingFormat::Wav)?;
+/// let vad = VoiceActivity::wrap(Box::new(recorder))
+///     .on_activity(|event| println!("{:?}", event));
+/// let analytics = vad.analytics();
+///
+/// handler.listen(Some(Box::new(vad)));
+///
+/// // Later...
+/// for (ssrc, stats) in analytics.snapshot() {
+///     println!("{}: talked for {:?}", ssrc, stats.talk_time);
+/// }
+/// ```
+///
+/// [`AudioReceiver`]: trait.AudioReceiver.html
+/// [`Recorder`]: struct.Recorder.html
+/// [`hangover`]: #method.hangover
+pub struct VoiceActivity {
+    analytics: SpeakingAnalytics,
+    callback: Option<ActivityCallback>,
+    detectors: HashMap<u32, Detector>,
+    hangover: Duration,
+    inner: Option<Box<AudioReceiver>>,
+    threshold: f32,
+    users: HashMap<u32, u64>,
+}
+
+impl VoiceActivity {
+    /// Creates a detector which doesn't pass audio on to any other receiver.
+    pub fn new() -> Self {
+        Self {
+            analytics: SpeakingAnalytics::default(),
+            callback: None,
+            detectors: HashMap::new(),
+            hangover: Duration::from_millis(300),
+            inner: None,
+            threshold: 0.01,
+            users: HashMap::new(),
+        }
+    }
+
+    /// Creates a detector which passes every call on to `inner`.
+    pub fn wrap(inner: Box<AudioReceiver>) -> Self {
+        Self {
+            inner: Some(inner),
+            ..Self::new()
+        }
+    }
+
+    /// Sets the RMS loudness, as a fraction of full scale, above which a frame
+    /// is considered to contain speech.
+    ///
+    /// Defaults to `0.01`, or around -40dBFS.
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+
+        self
+    }
+
+    /// Sets how long a user must be quiet for before they are considered to
+    /// have stopped talking.
+    ///
+    /// Defaults to 300ms.
+    pub fn hangover(mut self, hangover: Duration) -> Self {
+        self.hangover = hangover;
+
+        self
+    }
+
+    /// Sets a callback to be run whenever a user starts or stops talking.
+    ///
+    /// The callback is run on the voice thread, so should not block.
+    pub fn on_activity<F>(mut self, callback: F) -> Self
+        where F: FnMut(ActivityEvent) + Send + 'static {
+        self.callback = Some(Box::new(callback));
+
+        self
+    }
+
+    /// Retrieves a handle to the statistics gathered by this detector.
+    pub fn analytics(&self) -> SpeakingAnalytics { self.analytics.clone() }
+
+    fn hangover_frames(&self) -> u32 {
+        duration_to_frames(self.hangover).max(1) as u32
+    }
+
+    fn analyse(&mut self, ssrc: u32, timestamp: u32, data: &[i16]) {
+        let now = Instant::now();
+        let hangover_frames = self.hangover_frames();
+
+        let rms = rms(data);
+        let peak = data
+            .iter()
+            .map(|&sample| (f32::from(sample) / 32768.0).abs())
+            .fold(0f32, f32::max);
+        let loud = rms >= self.threshold;
+
+        let user_id = self.users.get(&ssrc).cloned();
+        let detector = self.detectors.entry(ssrc).or_default();
+        let mut analytics = self.analytics.inner.lock();
+        let stats = analytics.entry(ssrc).or_default();
+        stats.user_id = user_id;
+
+        let mut event = None;
+
+        if loud {
+            detector.loud_frames += 1;
+
+            if detector.loud_frames == 1 {
+                detector.start = Some((timestamp, now));
+            }
+
+            if detector.talking {
+                // Short pauses within speech count towards the user's talk
+                // time, but a trailing pause does not.
+                let frames = u64::from(detector.quiet_frames) + 1;
+                stats.talk_time += Duration::from_millis(frames * FRAME_LEN_MS);
+            } else if detector.loud_frames >= ATTACK_FRAMES {
+                detector.talking = true;
+                stats.talk_time += Duration::from_millis(u64::from(ATTACK_FRAMES) * FRAME_LEN_MS);
+
+                // Safe, as the start is set on the first loud frame.
+                let (start_timestamp, start_at) = detector.start.unwrap();
+
+                event = Some(ActivityEvent::Started {
+                    ssrc,
+                    user_id,
+                    timestamp: start_timestamp,
+                    at: start_at,
+                });
+            }
+
+            detector.quiet_frames = 0;
+            detector.last_loud = Some((timestamp, now));
+        } else if detector.talking {
+            detector.quiet_frames += 1;
+
+            if detector.quiet_frames >= hangover_frames {
+                event = detector.stop(ssrc, user_id);
+            }
+        } else {
+            detector.loud_frames = 0;
+        }
+
+        if detector.talking && loud {
+            stats.sum_squares += f64::from(rms) * f64::from(rms) * data.len() as f64;
+            stats.samples += data.len() as u64;
+            stats.peak = stats.peak.max(peak);
+        }
+
+        stats.talking = detector.talking;
+
+        drop(analytics);
+
+        if let Some(event) = event {
+            self.fire(event);
+        }
+    }
+
+    /// Stops every user who has sent nothing for the hangover period, or only
+    /// `ssrc` if given.
+    fn stop_stale(&mut self, only: Option<u32>) {
+        let now = Instant::now();
+        let hangover = self.hangover;
+        let mut events = Vec::new();
+
+        for (&ssrc, detector) in &mut self.detectors {
+            if !detector.talking {
+                continue;
+            }
+
+            let stale = match only {
+                Some(only) => only == ssrc,
+                None => match detector.last_loud {
+                    Some((_, at)) => now.duration_since(at) >= hangover,
+                    None => true,
+                },
+            };
+
+            if stale {
+                if let Some(event) = detector.stop(ssrc, self.users.get(&ssrc).cloned()) {
+                    events.push(event);
+                }
+
+                if let Some(stats) = self.analytics.inner.lock().get_mut(&ssrc) {
+                    stats.talking = false;
+                }
+            }
+        }
+
+        for event in events {
+            self.fire(event);
+        }
+    }
+
+    fn fire(&mut self, event: ActivityEvent) {
+        if let Some(ref mut callback) = self.callback {
+            callback(event);
+        }
+    }
+}
+
+impl Default for VoiceActivity {
+    fn default() -> Self { Self::new() }
+}
+
+impl Detector {
+    fn stop(&mut self, ssrc: u32, user_id: Option<u64>) -> Option<ActivityEvent> {
+        let start = self.start.take();
+        let last_loud = self.last_loud.take();
+
+        self.talking = false;
+        self.loud_frames = 0;
+        self.quiet_frames = 0;
+
+        let ((_, start_at), (timestamp, at)) = match (start, last_loud) {
+            (Some(start), Some(last_loud)) => (start, last_loud),
+            _ => return None,
+        };
+
+        Some(ActivityEvent::Stopped {
+            ssrc,
+            user_id,
+            timestamp,
+            at,
+            duration: at.duration_since(start_at) + Duration::from_millis(FRAME_LEN_MS),
+        })
+    }
+}
+
+impl AudioReceiver for VoiceActivity {
+    fn speaking_update(&mut self, ssrc: u32, user_id: u64, speaking: bool) {
+        self.users.insert(ssrc, user_id);
+
+        if !speaking {
+            self.stop_stale(Some(ssrc));
+        }
+
+        if let Some(ref mut inner) = self.inner {
+            inner.speaking_update(ssrc, user_id, speaking);
+        }
+    }
+
+    fn voice_packet(&mut self,
+                    ssrc: u32,
+                    sequence: u16,
+                    timestamp: u32,
+                    stereo: bool,
+                    data: &[i16]) {
+        self.analyse(ssrc, timestamp, data);
+
+        if let Some(ref mut inner) = self.inner {
+            inner.voice_packet(ssrc, sequence, timestamp, stereo, data);
+        }
+    }
+
+    fn opus_packet(&mut self, ssrc: u32, sequence: u16, timestamp: u32, data: &[u8]) {
+        if let Some(ref mut inner) = self.inner {
+            inner.opus_packet(ssrc, sequence, timestamp, data);
+        }
+    }
+
+    fn tick(&mut self) {
+        // Users who stop sending entirely are stopped once their hangover
+        // runs out, rather than when someone else next talks.
+        self.stop_stale(None);
+
+        if let Some(ref mut inner) = self.inner {
+            inner.tick();
+        }
+    }
+
+    fn speaking_state(&mut self, ssrc: u32, user_id: u64, state: SpeakingState) {
+        if let Some(ref mut inner) = self.inner {
+            inner.speaking_state(ssrc, user_id, state);
+        }
+    }
+
+    fn client_connect(&mut self, ssrc: u32, user_id: u64) {
+        self.users.insert(ssrc, user_id);
+
+        if let Some(ref mut inner) = self.inner {
+            inner.client_connect(ssrc, user_id);
+        }
+    }
+
+    fn client_disconnect(&mut self, user_id: u64) {
+        let ssrcs = self.users
+            .iter()
+            .filter(|&(_, &id)| id == user_id)
+            .map(|(&ssrc, _)| ssrc)
+            .collect::<Vec<_>>();
+
+        for ssrc in ssrcs {
+            self.stop_stale(Some(ssrc));
+            self.detectors.remove(&ssrc);
+            self.users.remove(&ssrc);
+        }
+
+        if let Some(ref mut inner) = self.inner {
+            inner.client_disconnect(user_id);
+        }
+    }
+
+    fn region_changed(&mut self, old_endpoint: &str, new_endpoint: &str) {
+        // None of the old SSRCs will be heard from again.
+        let ssrcs = self.detectors.keys().cloned().collect::<Vec<_>>();
+
+        for ssrc in ssrcs {
+            self.stop_stale(Some(ssrc));
+        }
+
+        self.detectors.clear();
+        self.users.clear();
+
+        if let Some(ref mut inner) = self.inner {
+            inner.region_changed(old_endpoint, new_endpoint);
+        }
+    }
+}
+
+/// The RMS loudness of some audio, as a fraction of full scale.
+pub(crate) fn rms(data: &[i16]) -> f32 {
+    if data.is_empty() {
+        return 0.0;
+    }
+
+    let sum_squares = data
+        .iter()
+        .map(|&sample| {
+            let sample = f64::from(sample) / 32768.0;
+
+            sample * sample
+        })
+        .sum::<f64>();
+
+    (sum_squares / data.len() as f64).sqrt() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOUD: [i16; 960] = [8192; 960];
+    const QUIET: [i16; 960] = [0; 960];
+
+    fn detector() -> (VoiceActivity, Arc<Mutex<Vec<ActivityEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+
+        let vad = VoiceActivity::new()
+            .hangover(Duration::from_millis(60))
+            .on_activity(move |event| sink.lock().push(event));
+
+        (vad, events)
+    }
+
+    #[test]
+    fn single_loud_frame_is_ignored() {
+        let (mut vad, events) = detector();
+
+        vad.analyse(1, 0, &LOUD);
+        vad.analyse(1, 960, &QUIET);
+        vad.analyse(1, 1920, &LOUD);
+
+        assert!(events.lock().is_empty());
+        assert!(!vad.analytics().get(1).unwrap().talking);
+    }
+
+    #[test]
+    fn speech_starts_from_first_loud_frame() {
+        let (mut vad, events) = detector();
+
+        vad.analyse(1, 0, &QUIET);
+        vad.analyse(1, 960, &LOUD);
+        assert!(events.lock().is_empty());
+
+        vad.analyse(1, 1920, &LOUD);
+
+        match events.lock()[..] {
+            [ActivityEvent::Started { ssrc: 1, timestamp: 960, .. }] => {},
+            ref other => panic!("unexpected events: {:?}", other),
+        }
+
+        let stats = vad.analytics().get(1).unwrap();
+        assert!(stats.talking);
+        assert_eq!(stats.talk_time, Duration::from_millis(2 * FRAME_LEN_MS));
+    }
+
+    #[test]
+    fn speech_stops_after_hangover() {
+        let (mut vad, events) = detector();
+
+        vad.analyse(1, 0, &LOUD);
+        vad.analyse(1, 960, &LOUD);
+
+        // A pause shorter than the hangover counts towards talk time.
+        vad.analyse(1, 1920, &QUIET);
+        vad.analyse(1, 2880, &LOUD);
+        assert_eq!(vad.analytics().get(1).unwrap().talk_time,
+                   Duration::from_millis(4 * FRAME_LEN_MS));
+
+        vad.analyse(1, 3840, &QUIET);
+        vad.analyse(1, 4800, &QUIET);
+        assert_eq!(events.lock().len(), 1);
+
+        vad.analyse(1, 5760, &QUIET);
+
+        match events.lock()[..] {
+            [ActivityEvent::Started { .. },
+             ActivityEvent::Stopped { ssrc: 1, timestamp: 2880, .. }] => {},
+            ref other => panic!("unexpected events: {:?}", other),
+        }
+
+        let stats = vad.analytics().get(1).unwrap();
+        assert!(!stats.talking);
+        assert_eq!(stats.talk_time, Duration::from_millis(4 * FRAME_LEN_MS));
+    }
+}
@@ -0,0 +1,418 @@
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use internal::prelude::*;
+use std::{
+    io::{
+        ErrorKind as IoErrorKind,
+        Read,
+        Result as IoResult,
+        Seek,
+        SeekFrom,
+        Write
+    },
+    result::Result as StdResult
+};
+use super::audio::SAMPLE_RATE;
+use super::WavError;
+
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_FLOAT: u16 = 0x0003;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Length of the RIFF and format headers, up to the start of the sample data.
+const HEADER_LEN: u32 = 44;
+
+/// Greatest length of a format chunk which will be read. The longest in use,
+/// for `WAVE_FORMAT_EXTENSIBLE`, is 40 bytes.
+const MAX_FMT_LEN: u32 = 64;
+
+/// Written repeatedly to fill gaps with silence.
+static SILENCE: [u8; 4096] = [0; 4096];
+
+/// Writes 16-bit PCM audio at 48kHz into a WAV file.
+///
+/// The lengths in the file's header are filled in by [`finish`].
+///
+/// [`finish`]: #method.finish
+pub(crate) struct WavWriter<W: Write + Seek> {
+    channels: u16,
+    data_len: u32,
+    inner: W,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub(crate) fn new(mut inner: W, channels: u16) -> Result<Self> {
+        write_header(&mut inner, channels, 0)?;
+
+        Ok(Self {
+            channels,
+            data_len: 0,
+            inner,
+        })
+    }
+
+    /// Appends interleaved samples to the file.
+    pub(crate) fn write_samples(&mut self, samples: &[i16]) -> Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+
+        for &sample in samples {
+            bytes.write_i16::<LittleEndian>(sample)?;
+        }
+
+        self.inner.write_all(&bytes)?;
+        self.data_len = self.data_len.saturating_add(bytes.len() as u32);
+
+        Ok(())
+    }
+
+    /// Appends `frames` samples of silence on every channel.
+    pub(crate) fn write_silence(&mut self, frames: usize) -> Result<()> {
+        let mut remaining = frames * self.channels as usize * 2;
+
+        while remaining > 0 {
+            let len = remaining.min(SILENCE.len());
+
+            self.inner.write_all(&SILENCE[..len])?;
+            self.data_len = self.data_len.saturating_add(len as u32);
+            remaining -= len;
+        }
+
+        Ok(())
+    }
+
+    /// Fills in the header with the final length of the file.
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        self.inner.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.inner, self.channels, self.data_len)?;
+        self.inner.seek(SeekFrom::End(0))?;
+        self.inner.flush()?;
+
+        Ok(())
+    }
+}
+
+fn write_header<W: Write>(writer: &mut W, channels: u16, data_len: u32) -> Result<()> {
+    let block_align = channels * 2;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_u32::<LittleEndian>((HEADER_LEN - 8).saturating_add(data_len))?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_u32::<LittleEndian>(16)?;
+    // PCM.
+    writer.write_u16::<LittleEndian>(1)?;
+    writer.write_u16::<LittleEndian>(channels)?;
+    writer.write_u32::<LittleEndian>(SAMPLE_RATE)?;
+    writer.write_u32::<LittleEndian>(SAMPLE_RATE * u32::from(block_align))?;
+    writer.write_u16::<LittleEndian>(block_align)?;
+    writer.write_u16::<LittleEndian>(16)?;
+
+    writer.write_all(b"data")?;
+    writer.write_u32::<LittleEndian>(data_len)?;
+
+    Ok(())
+}
+
+/// The encoding of each sample in a WAV file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum SampleFormat {
+    U8,
+    I16,
+    I24,
+    I32,
+    F32,
+}
+
+/// Reads the samples of a WAV file as floating point frames.
+pub(crate) struct WavReader<R: Read + Seek> {
+    block_align: u64,
+    channels: u16,
+    /// Offset of the first sample within the reader.
+    data_start: u64,
+    /// Length of the sample data, in bytes.
+    data_len: u64,
+    format: SampleFormat,
+    inner: R,
+    /// Bytes of sample data left to be read.
+    remaining: u64,
+    sample_rate: u32,
+}
+
+impl<R: Read + Seek> WavReader<R> {
+    /// Parses the file's RIFF headers, leaving the reader at the first sample.
+    pub(crate) fn new(mut inner: R) -> StdResult<Self, WavError> {
+        let mut tag = [0u8; 4];
+
+        inner.read_exact(&mut tag).map_err(WavError::IoError)?;
+
+        if &tag != b"RIFF" {
+            return Err(WavError::InvalidHeader);
+        }
+
+        inner.read_u32::<LittleEndian>().map_err(WavError::IoError)?;
+        inner.read_exact(&mut tag).map_err(WavError::IoError)?;
+
+        if &tag != b"WAVE" {
+            return Err(WavError::InvalidHeader);
+        }
+
+        let mut fmt = None;
+
+        loop {
+            match inner.read_exact(&mut tag) {
+                Ok(()) => {},
+                Err(ref e) if e.kind() == IoErrorKind::UnexpectedEof => {
+                    return Err(WavError::InvalidHeader);
+                },
+                Err(e) => return Err(WavError::IoError(e)),
+            }
+
+            let len = inner.read_u32::<LittleEndian>().map_err(WavError::IoError)?;
+
+            match &tag {
+                b"fmt " => {
+                    if len > MAX_FMT_LEN {
+                        return Err(WavError::InvalidHeader);
+                    }
+
+                    let mut chunk = [0u8; MAX_FMT_LEN as usize];
+                    let chunk = &mut chunk[..len as usize];
+                    inner.read_exact(chunk).map_err(WavError::IoError)?;
+
+                    fmt = Some(parse_fmt(chunk)?);
+
+                    if len % 2 == 1 {
+                        inner.seek(SeekFrom::Current(1)).map_err(WavError::IoError)?;
+                    }
+                },
+                b"data" => {
+                    let (channels, sample_rate, format, block_align) = match fmt {
+                        Some(fmt) => fmt,
+                        None => return Err(WavError::InvalidHeader),
+                    };
+
+                    let data_start = inner.seek(SeekFrom::Current(0)).map_err(WavError::IoError)?;
+
+                    // A truncated file holds less data than its header claims.
+                    let end = inner.seek(SeekFrom::End(0)).map_err(WavError::IoError)?;
+                    inner.seek(SeekFrom::Start(data_start)).map_err(WavError::IoError)?;
+
+                    let data_len = u64::from(len).min(end.saturating_sub(data_start));
+
+                    return Ok(Self {
+                        block_align: u64::from(block_align),
+                        channels,
+                        data_start,
+                        data_len,
+                        format,
+                        inner,
+                        remaining: data_len,
+                        sample_rate,
+                    });
+                },
+                _ => {
+                    // Chunks are padded to an even length.
+                    let skip = i64::from(len) + i64::from(len % 2);
+
+                    inner.seek(SeekFrom::Current(skip)).map_err(WavError::IoError)?;
+                },
+            }
+        }
+    }
+
+    pub(crate) fn is_stereo(&self) -> bool { self.channels == 2 }
+
+    pub(crate) fn sample_rate(&self) -> u32 { self.sample_rate }
+
+    /// The number of frames of samples in the file.
+    pub(crate) fn frame_count(&self) -> u64 { self.data_len / self.block_align.max(1) }
+
+    /// Reads the next frame of samples, scaled to lie between `-1.0` and
+    /// `1.0`. Mono frames only use the first channel.
+    ///
+    /// Returns `None` once the sample data has ended.
+    pub(crate) fn read_frame(&mut self) -> IoResult<Option<[f32; 2]>> {
+        if self.remaining < self.block_align {
+            return Ok(None);
+        }
+
+        let mut frame = [0f32; 2];
+
+        for sample in frame.iter_mut().take(self.channels as usize) {
+            *sample = match self.format {
+                SampleFormat::U8 => (f32::from(self.inner.read_u8()?) - 128.0) / 128.0,
+                SampleFormat::I16 => f32::from(self.inner.read_i16::<LittleEndian>()?) / 32_768.0,
+                SampleFormat::I24 => self.inner.read_i24::<LittleEndian>()? as f32 / 8_388_608.0,
+                SampleFormat::I32 => {
+                    self.inner.read_i32::<LittleEndian>()? as f32 / 2_147_483_648.0
+                },
+                SampleFormat::F32 => self.inner.read_f32::<LittleEndian>()?,
+            };
+        }
+
+        self.remaining -= self.block_align;
+
+        Ok(Some(frame))
+    }
+
+    /// Moves to the given frame of the sample data, or its end if the file is
+    /// shorter.
+    pub(crate) fn seek_frame(&mut self, frame: u64) -> IoResult<()> {
+        let offset = (frame * self.block_align).min(self.data_len);
+
+        self.inner.seek(SeekFrom::Start(self.data_start + offset))?;
+        self.remaining = self.data_len - offset;
+
+        Ok(())
+    }
+}
+
+/// Reads the channel count, sample rate, sample format and block alignment
+/// from a `fmt ` chunk.
+fn parse_fmt(chunk: &[u8]) -> StdResult<(u16, u32, SampleFormat, u16), WavError> {
+    if chunk.len() < 16 {
+        return Err(WavError::InvalidHeader);
+    }
+
+    let mut reader = chunk;
+
+    // Reading from a long enough slice can't fail.
+    let mut tag = reader.read_u16::<LittleEndian>().unwrap();
+    let channels = reader.read_u16::<LittleEndian>().unwrap();
+    let sample_rate = reader.read_u32::<LittleEndian>().unwrap();
+    let _byte_rate = reader.read_u32::<LittleEndian>().unwrap();
+    let block_align = reader.read_u16::<LittleEndian>().unwrap();
+    let bits = reader.read_u16::<LittleEndian>().unwrap();
+
+    // The real format of an extensible file is held in the first two bytes of
+    // its subformat GUID.
+    if tag == FORMAT_EXTENSIBLE {
+        if chunk.len() < 26 {
+            return Err(WavError::InvalidHeader);
+        }
+
+        tag = LittleEndian::read_u16(&chunk[24..26]);
+    }
+
+    let format = match (tag, bits) {
+        (FORMAT_PCM, 8) => SampleFormat::U8,
+        (FORMAT_PCM, 16) => SampleFormat::I16,
+        (FORMAT_PCM, 24) => SampleFormat::I24,
+        (FORMAT_PCM, 32) => SampleFormat::I32,
+        (FORMAT_FLOAT, 32) => SampleFormat::F32,
+        (tag, bits) => return Err(WavError::UnsupportedFormat(tag, bits)),
+    };
+
+    if channels == 0 || channels > 2 {
+        return Err(WavError::UnsupportedChannels(channels));
+    }
+
+    if sample_rate == 0 || u32::from(block_align) != u32::from(channels) * u32::from(bits / 8) {
+        return Err(WavError::InvalidHeader);
+    }
+
+    Ok((channels, sample_rate, format, block_align))
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use std::io::Cursor;
+    use super::*;
+
+    fn fmt_chunk(tag: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * (bits / 8);
+        let mut chunk = Vec::new();
+
+        chunk.write_u16::<LittleEndian>(tag).unwrap();
+        chunk.write_u16::<LittleEndian>(channels).unwrap();
+        chunk.write_u32::<LittleEndian>(sample_rate).unwrap();
+        chunk.write_u32::<LittleEndian>(sample_rate * u32::from(block_align)).unwrap();
+        chunk.write_u16::<LittleEndian>(block_align).unwrap();
+        chunk.write_u16::<LittleEndian>(bits).unwrap();
+
+        chunk
+    }
+
+    #[test]
+    fn fmt_is_parsed() {
+        let (channels, sample_rate, format, block_align) =
+            parse_fmt(&fmt_chunk(FORMAT_PCM, 2, 44_100, 16)).unwrap();
+
+        assert_eq!((channels, sample_rate, format, block_align), (2, 44_100, SampleFormat::I16, 4));
+
+        let (_, _, format, _) = parse_fmt(&fmt_chunk(FORMAT_FLOAT, 1, 48_000, 32)).unwrap();
+        assert_eq!(format, SampleFormat::F32);
+
+        // An extensible header names its real format in its subformat.
+        let mut extensible = fmt_chunk(FORMAT_EXTENSIBLE, 2, 48_000, 24);
+        extensible.extend_from_slice(&[22, 0, 24, 0, 3, 0, 0, 0]);
+        extensible.extend_from_slice(&[1, 0, 0, 0, 0, 0, 16, 0, 128, 0, 0, 170, 0, 56, 155, 113]);
+
+        let (_, _, format, _) = parse_fmt(&extensible).unwrap();
+        assert_eq!(format, SampleFormat::I24);
+    }
+
+    #[test]
+    fn bad_fmt_is_rejected() {
+        match parse_fmt(&fmt_chunk(FORMAT_PCM, 6, 48_000, 16)) {
+            Err(WavError::UnsupportedChannels(6)) => {},
+            other => panic!("expected unsupported channels, got {:?}", other),
+        }
+
+        match parse_fmt(&fmt_chunk(FORMAT_PCM, 2, 48_000, 12)) {
+            Err(WavError::UnsupportedFormat(FORMAT_PCM, 12)) => {},
+            other => panic!("expected unsupported format, got {:?}", other),
+        }
+
+        match parse_fmt(&fmt_chunk(FORMAT_PCM, 2, 48_000, 16)[..14]) {
+            Err(WavError::InvalidHeader) => {},
+            other => panic!("expected an invalid header, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn oversized_fmt_is_rejected() {
+        let mut file = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        file.write_u32::<LittleEndian>(0xFFFF_FFF0).unwrap();
+
+        match WavReader::new(Cursor::new(file)) {
+            Err(WavError::InvalidHeader) => {},
+            Err(other) => panic!("expected an invalid header, got {:?}", other),
+            Ok(_) => panic!("expected an invalid header"),
+        }
+    }
+
+    #[test]
+    fn written_samples_are_read_back() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 2).unwrap();
+        writer.write_samples(&[16_384, -16_384]).unwrap();
+        writer.write_silence(2).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = WavReader::new(Cursor::new(writer.inner.into_inner())).unwrap();
+        assert!(reader.is_stereo());
+        assert_eq!(reader.sample_rate(), SAMPLE_RATE);
+        assert_eq!(reader.frame_count(), 3);
+
+        assert_eq!(reader.read_frame().unwrap(), Some([0.5, -0.5]));
+        assert_eq!(reader.read_frame().unwrap(), Some([0.0, 0.0]));
+        assert_eq!(reader.read_frame().unwrap(), Some([0.0, 0.0]));
+        assert_eq!(reader.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_data_is_clamped() {
+        let mut file = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        file.write_u32::<LittleEndian>(16).unwrap();
+        file.extend_from_slice(&fmt_chunk(FORMAT_PCM, 1, 48_000, 16));
+        file.extend_from_slice(b"data");
+        file.write_u32::<LittleEndian>(1_000_000).unwrap();
+        file.write_i16::<LittleEndian>(16_384).unwrap();
+
+        let mut reader = WavReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(reader.frame_count(), 1);
+        assert_eq!(reader.read_frame().unwrap(), Some([0.5, 0.0]));
+        assert_eq!(reader.read_frame().unwrap(), None);
+    }
+}
@@ -193,3 +193,33 @@ fn guild_features_deser() {
 fn guild_system_channel_id_missing() {
     p!(Guild, "guild_system_channel_id_missing");
 }
+
+#[test]
+fn voice_resumed() {
+    p!(VoiceEvent, "voice_resumed_1");
+}
+
+#[test]
+fn voice_client_connect() {
+    p!(VoiceEvent, "voice_client_connect_1");
+}
+
+#[test]
+fn voice_client_disconnect() {
+    p!(VoiceEvent, "voice_client_disconnect_1");
+}
+
+#[test]
+fn voice_speaking() {
+    p!(VoiceEvent, "voice_speaking_1");
+    p!(VoiceEvent, "voice_speaking_2");
+}
+
+#[cfg(feature = "voice")]
+#[test]
+fn dca_metadata() {
+    use serenity::voice::DcaMetadata;
+
+    p!(DcaMetadata, "dca_metadata_1");
+    p!(DcaMetadata, "dca_metadata_2");
+}